@@ -0,0 +1,165 @@
+use std::{fmt, path::PathBuf, str::FromStr};
+
+use serde::{de, Deserialize, Deserializer};
+
+/// Where a listener accepts connections, written as:
+///
+/// - `host:port`, e.g. `127.0.0.1:9000` or `[::1]:9000`
+/// - `unix:<path>`, a Unix domain socket, e.g. `unix:/run/jaya/jaya.sock`
+/// - `systemd`, the first socket passed by systemd socket activation not
+///   taken by another address, or `systemd:<name>` for the one of the
+///   `.socket` unit with `FileDescriptorName=<name>`
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Address {
+    Tcp(String),
+    Unix(PathBuf),
+    Systemd(Option<String>),
+}
+
+impl Address {
+    pub fn tcp(host: &str, port: u16) -> Self {
+        match host.contains(':') {
+            true => Address::Tcp(format!("[{}]:{}", host, port)),
+            false => Address::Tcp(format!("{}:{}", host, port)),
+        }
+    }
+
+    /// The port of a TCP address.
+    pub fn port(&self) -> Option<u16> {
+        match self {
+            Address::Tcp(address) => address.rsplit_once(':')?.1.parse().ok(),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for Address {
+    type Err = String;
+
+    fn from_str(address: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = address.strip_prefix("unix:") {
+            return match path.is_empty() {
+                true => Err("`unix:` needs the path of the socket".to_string()),
+                false => Ok(Address::Unix(PathBuf::from(path))),
+            };
+        }
+        if address == "systemd" {
+            return Ok(Address::Systemd(None));
+        }
+        if let Some(name) = address.strip_prefix("systemd:") {
+            return Ok(Address::Systemd(Some(name.to_string())));
+        }
+
+        match address.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {
+                Ok(Address::Tcp(address.to_string()))
+            }
+            _ => Err(format!(
+                "`{}` is not `host:port`, `unix:<path>` or `systemd[:<name>]`",
+                address
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Address::Tcp(address) => write!(f, "{}", address),
+            Address::Unix(path) => write!(f, "unix:{}", path.display()),
+            Address::Systemd(None) => write!(f, "systemd"),
+            Address::Systemd(Some(name)) => write!(f, "systemd:{}", name),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Address {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(address: &str) -> Result<Address, String> {
+        address.parse()
+    }
+
+    #[test]
+    fn parses_tcp_addresses() {
+        assert_eq!(
+            parse("127.0.0.1:9000"),
+            Ok(Address::Tcp("127.0.0.1:9000".to_string()))
+        );
+        assert_eq!(
+            parse("localhost:9000"),
+            Ok(Address::Tcp("localhost:9000".to_string()))
+        );
+        assert_eq!(
+            parse("[::1]:9000"),
+            Ok(Address::Tcp("[::1]:9000".to_string()))
+        );
+        for address in [
+            "localhost",
+            ":9000",
+            "localhost:",
+            "localhost:65536",
+            "host:http",
+        ] {
+            assert!(parse(address).is_err(), "{}", address);
+        }
+    }
+
+    #[test]
+    fn parses_unix_addresses() {
+        assert_eq!(
+            parse("unix:/run/jaya/jaya.sock"),
+            Ok(Address::Unix(PathBuf::from("/run/jaya/jaya.sock")))
+        );
+        assert_eq!(
+            parse("unix:jaya.sock"),
+            Ok(Address::Unix(PathBuf::from("jaya.sock")))
+        );
+        assert!(parse("unix:").is_err());
+    }
+
+    #[test]
+    fn parses_systemd_addresses() {
+        assert_eq!(parse("systemd"), Ok(Address::Systemd(None)));
+        assert_eq!(
+            parse("systemd:web"),
+            Ok(Address::Systemd(Some("web".to_string())))
+        );
+    }
+
+    #[test]
+    fn displays_as_parsed() {
+        for address in [
+            "127.0.0.1:9000",
+            "[::1]:9000",
+            "unix:/run/jaya/jaya.sock",
+            "systemd",
+            "systemd:web",
+        ] {
+            assert_eq!(parse(address).unwrap().to_string(), address);
+        }
+    }
+
+    #[test]
+    fn builds_tcp_addresses_from_host_and_port() {
+        assert_eq!(
+            Address::tcp("0.0.0.0", 3000),
+            Address::Tcp("0.0.0.0:3000".to_string())
+        );
+        assert_eq!(
+            Address::tcp("::", 3000),
+            Address::Tcp("[::]:3000".to_string())
+        );
+        assert_eq!(Address::tcp("::", 3000).port(), Some(3000));
+        assert_eq!(Address::Systemd(None).port(), None);
+    }
+}
@@ -0,0 +1,94 @@
+use std::path::PathBuf;
+
+use crate::Issue;
+
+#[derive(Debug)]
+pub enum Error {
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    UnsupportedFormat(PathBuf),
+    Parse {
+        path: PathBuf,
+        key: Option<String>,
+        line: Option<usize>,
+        column: Option<usize>,
+        message: String,
+    },
+    Env {
+        var: String,
+        message: String,
+    },
+    Invalid {
+        key: Option<String>,
+        message: String,
+    },
+    Validation(Vec<Issue>),
+    Watch(notify::Error),
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::Watch(source) => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Io { path, source } => {
+                write!(
+                    f,
+                    "Failed to read config file {}: {}",
+                    path.display(),
+                    source
+                )
+            }
+            Error::UnsupportedFormat(path) => write!(
+                f,
+                "Unsupported config file format {} (expected .toml, .yaml, .yml or .json)",
+                path.display()
+            ),
+            Error::Parse {
+                path,
+                key,
+                line,
+                column,
+                message,
+            } => {
+                write!(f, "{}", path.display())?;
+                if let Some(line) = line {
+                    write!(f, ":{}", line)?;
+                    if let Some(column) = column {
+                        write!(f, ":{}", column)?;
+                    }
+                }
+                write!(f, ": {}", message)?;
+                if let Some(key) = key {
+                    write!(f, " (at key `{}`)", key)?;
+                }
+                Ok(())
+            }
+            Error::Env { var, message } => {
+                write!(f, "Invalid environment variable {}: {}", var, message)
+            }
+            Error::Invalid { key, message } => match key {
+                Some(key) => write!(f, "Invalid config value at key `{}`: {}", key, message),
+                None => write!(f, "Invalid config: {}", message),
+            },
+            Error::Validation(issues) => {
+                write!(f, "Invalid config:")?;
+                for issue in issues {
+                    write!(f, "\n  - {}", issue)?;
+                }
+                Ok(())
+            }
+            Error::Watch(e) => write!(f, "Failed to watch config files: {}", e),
+        }
+    }
+}
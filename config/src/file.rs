@@ -0,0 +1,190 @@
+use std::path::Path;
+
+use serde::de::DeserializeOwned;
+
+use crate::Error;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Format {
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl Format {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "toml" => Some(Format::Toml),
+            "yaml" | "yml" => Some(Format::Yaml),
+            "json" => Some(Format::Json),
+            _ => None,
+        }
+    }
+}
+
+pub fn read(path: &Path) -> Result<(Format, String), Error> {
+    let format = Format::from_path(path).ok_or_else(|| Error::UnsupportedFormat(path.into()))?;
+    let text = std::fs::read_to_string(path).map_err(|source| Error::Io {
+        path: path.into(),
+        source,
+    })?;
+    Ok((format, text))
+}
+
+/// Deserializes `text` with the native deserializer of `format`, so errors
+/// carry both the offending key and its position in the file.
+pub fn parse<T: DeserializeOwned>(path: &Path, format: Format, text: &str) -> Result<T, Error> {
+    match format {
+        Format::Toml => {
+            serde_path_to_error::deserialize(toml::Deserializer::new(text)).map_err(|err| {
+                let key = key_of(err.path());
+                let inner = err.into_inner();
+                let (line, column) = match inner.span() {
+                    Some(span) => {
+                        let (line, column) = position(text, span.start);
+                        (Some(line), Some(column))
+                    }
+                    None => (None, None),
+                };
+                Error::Parse {
+                    path: path.into(),
+                    key,
+                    line,
+                    column,
+                    message: inner.message().to_string(),
+                }
+            })
+        }
+        Format::Yaml => serde_path_to_error::deserialize(serde_yaml::Deserializer::from_str(text))
+            .map_err(|err| {
+                let key = key_of(err.path());
+                let inner = err.into_inner();
+                let location = inner.location();
+                // serde_yaml prefixes the message with the key path itself.
+                let message = strip_location(&inner.to_string());
+                let message = match &key {
+                    Some(key) => message
+                        .strip_prefix(&format!("{}: ", key))
+                        .map(str::to_string)
+                        .unwrap_or(message),
+                    None => message,
+                };
+                Error::Parse {
+                    path: path.into(),
+                    key,
+                    line: location.as_ref().map(|l| l.line()),
+                    column: location.as_ref().map(|l| l.column()),
+                    message,
+                }
+            }),
+        Format::Json => {
+            let mut de = serde_json::Deserializer::from_str(text);
+            serde_path_to_error::deserialize(&mut de).map_err(|err| {
+                let key = key_of(err.path());
+                let inner = err.into_inner();
+                Error::Parse {
+                    path: path.into(),
+                    key,
+                    line: Some(inner.line()).filter(|line| *line > 0),
+                    column: Some(inner.column()).filter(|column| *column > 0),
+                    message: strip_location(&inner.to_string()),
+                }
+            })
+        }
+    }
+}
+
+fn key_of(path: &serde_path_to_error::Path) -> Option<String> {
+    let key = path.to_string();
+    if key == "." {
+        None
+    } else {
+        Some(key)
+    }
+}
+
+fn position(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset.min(text.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+    (line, column)
+}
+
+fn strip_location(message: &str) -> String {
+    match message.rfind(" at line ") {
+        Some(index) => message[..index].to_string(),
+        None => message.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use crate::{Config, Error};
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(name)
+    }
+
+    fn parse_error(
+        name: &str,
+    ) -> (
+        PathBuf,
+        Option<String>,
+        Option<usize>,
+        Option<usize>,
+        String,
+    ) {
+        match Config::load_config_file(fixture(name)) {
+            Err(Error::Parse {
+                path,
+                key,
+                line,
+                column,
+                message,
+            }) => (path, key, line, column, message),
+            other => panic!("expected a parse error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn reports_the_malformed_key_of_toml() {
+        let (path, key, line, column, message) = parse_error("malformed.toml");
+        assert_eq!(path, fixture("malformed.toml"));
+        assert_eq!(key.as_deref(), Some("server.port"));
+        assert_eq!((line, column), (Some(3), Some(8)));
+        assert!(message.contains("u16"), "{}", message);
+    }
+
+    #[test]
+    fn reports_the_malformed_key_of_yaml() {
+        let (path, key, line, column, message) = parse_error("malformed.yaml");
+        assert_eq!(path, fixture("malformed.yaml"));
+        assert_eq!(key.as_deref(), Some("server.port"));
+        assert_eq!((line, column), (Some(3), Some(9)));
+        assert!(message.contains("u16"), "{}", message);
+    }
+
+    #[test]
+    fn reports_the_malformed_key_of_json() {
+        let (path, key, line, column, message) = parse_error("malformed.json");
+        assert_eq!(path, fixture("malformed.json"));
+        assert_eq!(key.as_deref(), Some("server.port"));
+        assert_eq!((line, column), (Some(4), Some(28)));
+        assert!(message.contains("u16"), "{}", message);
+    }
+
+    #[test]
+    fn rejects_unknown_extensions() {
+        match Config::load_config_file(fixture("config.ini")) {
+            Err(Error::UnsupportedFormat(path)) => assert_eq!(path, fixture("config.ini")),
+            other => panic!(
+                "expected an unsupported format, got {:?}",
+                other.map(|_| ())
+            ),
+        }
+    }
+}
@@ -1,57 +1,442 @@
+mod address;
+mod error;
+mod file;
+mod loader;
+mod secret;
+mod section;
+mod validate;
+mod value;
+mod watch;
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
 use serde::Deserialize;
+use serde_json::Value;
+
+pub use crate::address::Address;
+pub use crate::error::Error;
+pub use crate::loader::{Loader, DEFAULT_ENV_PREFIX};
+pub use crate::secret::Secret;
+pub use crate::section::Section;
+pub use crate::validate::Issue;
+pub use crate::watch::{Check, Watcher};
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Clone, Default)]
+#[serde(default)]
 pub struct Config {
+    pub app: App,
+    pub server: Server,
     pub database: Database,
+    pub log: Log,
+    /// Every loaded key, including those of application sections.
+    #[serde(skip)]
+    raw: Arc<Value>,
+}
+
+#[derive(Deserialize, Clone, PartialEq, Debug)]
+#[serde(default)]
+pub struct App {
+    pub name: String,
+    pub base_url: String,
+    /// Filled in by the [`Loader`] from `JAYA_ENV` when not set explicitly.
+    pub environment: String,
+}
+
+#[derive(Deserialize, Clone, PartialEq, Debug)]
+#[serde(default)]
+pub struct Server {
+    pub host: String,
+    pub port: u16,
+    /// Addresses the application listens on, replacing `host` and `port`
+    /// when not empty. See [`Address`] for the forms they take.
+    pub listen: Vec<Address>,
+    /// Listeners serving another router than the application, keyed by the
+    /// name it is registered under, e.g. an internal admin port.
+    pub listeners: BTreeMap<String, Listener>,
+    /// Number of prefork worker processes, `0` forks one per CPU.
+    pub workers: u32,
+    pub static_dir: String,
+    /// Maximum request body size in bytes.
+    pub body_limit: usize,
+    /// Seconds a request may take before answering `408`, `0` disables it.
+    pub request_timeout: u64,
+    /// Seconds a client has to send the request headers, `0` disables it.
+    pub header_read_timeout: u64,
+    /// Seconds in-flight requests get to finish on `SIGTERM`, or when a
+    /// reload replaces the workers, before their connections are closed.
+    pub shutdown_timeout: u64,
+    /// Serves HTTPS instead of HTTP on the TCP addresses of the
+    /// application when set.
+    pub tls: Option<Tls>,
+}
+
+#[derive(Deserialize, Clone, PartialEq, Debug, Default)]
+#[serde(default)]
+pub struct Listener {
+    pub listen: Vec<Address>,
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Clone, PartialEq, Debug)]
+#[serde(default)]
+pub struct Tls {
+    /// PEM file of the certificate chain, leaf first. Required, like `key`,
+    /// so that setting another `server.tls` key alone does not turn TLS on
+    /// with made-up paths.
+    pub cert: PathBuf,
+    /// PEM file of the private key, in PKCS#8, PKCS#1 or SEC1 form.
+    pub key: PathBuf,
+    /// Seconds between checks of both files for a renewed certificate,
+    /// which then replaces the current one for new connections. `0`
+    /// disables it.
+    pub reload_interval: u64,
+    /// Offers HTTP/2 to clients through ALPN, falling back to HTTP/1.1.
+    /// When off, HTTPS connections only speak HTTP/1.1.
+    pub http2: bool,
+    /// Also listens for plain HTTP on this port, redirecting every request
+    /// to HTTPS on the first TCP address of the application.
+    pub redirect_port: Option<u16>,
+}
+
+#[derive(Deserialize, Clone, PartialEq, Debug)]
+#[serde(default)]
+pub struct Log {
+    /// A filter directive such as `info` or `jaya=debug,sqlx=warn`.
+    pub level: String,
+    pub format: LogFormat,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Pretty,
+    Compact,
+    Json,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    #[default]
+    Debug,
+    Trace,
+}
+
+#[derive(Deserialize, Clone, PartialEq, Debug)]
+#[serde(default)]
 pub struct Database {
+    /// Must match the driver feature the `database` crate is built with.
+    pub driver: Driver,
+    /// The database name, or the file path with the `sqlite` driver.
     pub name: String,
     pub host: String,
+    /// Defaults to 5432 with the `postgres` driver and 3306 with `mysql`.
     pub port: u16,
+    /// Defaults to `postgres` with the `postgres` driver and `root` with
+    /// `mysql`.
     pub username: String,
-    pub password: String,
+    pub password: Secret,
+    /// Reads the password from this file instead, e.g. a Docker or
+    /// Kubernetes secret mounted at `/run/secrets/db`.
+    pub password_file: Option<PathBuf>,
+    /// A full connection URL, e.g. from `DATABASE_URL`. When set it takes
+    /// precedence over the individual connection fields.
+    pub url: Option<Secret>,
+    /// Pool size of each worker process, so a prefork server opens up to
+    /// `server.workers * max_connections` connections in total.
+    pub max_connections: u32,
+    pub min_connections: u32,
+    /// Seconds to wait for a free connection.
+    pub acquire_timeout: u64,
+    /// Seconds an unused connection is kept open, `0` keeps it forever.
+    pub idle_timeout: u64,
+    /// Seconds before a connection is recycled, `0` never recycles it.
+    pub max_lifetime: u64,
+    /// Seconds a statement may run before it is cancelled, `0` disables it.
+    /// Not supported by the `sqlite` driver.
+    pub statement_timeout: u64,
+    /// Level every statement is logged at, along with its duration and row
+    /// counts, under the `sqlx::query` target.
+    pub log_statements: LogLevel,
+    /// Milliseconds after which a statement is logged as slow, at `warn`.
+    /// `0` disables it.
+    pub slow_query_threshold: u64,
+    /// Times the same `SELECT` may run during one request before it is
+    /// reported as a likely N+1 query, outside of production. `0` disables
+    /// it.
+    pub n_plus_one_threshold: usize,
+    /// How many more times to try connecting on startup before giving up.
+    pub connect_retries: u32,
+    /// Seconds to wait before the first retry, at least 1, doubled after
+    /// every failed attempt up to 30 seconds.
+    pub connect_retry_delay: u64,
+    /// Overrides the `sslmode` of the connection when set.
+    pub ssl_mode: Option<SslMode>,
+    pub application_name: Option<String>,
+    /// Directory of the `<version>_<name>.up.sql` migration files.
+    pub migrations: PathBuf,
+    /// Directory of the `<order>_<name>.sql` and `.json` seed fixtures.
+    pub seeds: PathBuf,
+    /// Read replicas, sharing the credentials and pool settings above.
+    pub replicas: Vec<Replica>,
+}
+
+/// A read replica of the primary [`Database`]. Either the `url`, or the
+/// `host` and `port` that differ from the primary.
+#[derive(Deserialize, Clone, PartialEq, Debug, Default)]
+#[serde(default)]
+pub struct Replica {
+    pub url: Option<Secret>,
+    pub host: Option<String>,
+    pub port: Option<u16>,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Driver {
+    #[default]
+    Postgres,
+    Mysql,
+    Sqlite,
 }
 
-impl Default for Config {
+impl Driver {
+    /// The URL schemes of the driver.
+    pub fn schemes(&self) -> &'static [&'static str] {
+        match self {
+            Driver::Postgres => &["postgres://", "postgresql://"],
+            Driver::Mysql => &["mysql://", "mariadb://"],
+            Driver::Sqlite => &["sqlite:"],
+        }
+    }
+}
+
+impl std::fmt::Display for Driver {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Driver::Postgres => write!(f, "postgres"),
+            Driver::Mysql => write!(f, "mysql"),
+            Driver::Sqlite => write!(f, "sqlite"),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum SslMode {
+    Disable,
+    Allow,
+    Prefer,
+    Require,
+    VerifyCa,
+    VerifyFull,
+}
+
+impl std::fmt::Debug for Config {
+    // `raw` is left out since it holds secrets in plain text.
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Config")
+            .field("app", &self.app)
+            .field("server", &self.server)
+            .field("database", &self.database)
+            .field("log", &self.log)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for App {
     fn default() -> Self {
         Self {
-            database: Default::default(),
+            name: "Jaya".to_string(),
+            base_url: "http://localhost:3000".to_string(),
+            environment: "development".to_string(),
         }
     }
 }
 
-impl Default for Database {
+impl Default for Server {
     fn default() -> Self {
         Self {
-            name: "jaya".to_string(),
-            host: "localhost".to_string(),
-            port: 5432,
-            username: "postgres".to_string(),
-            password: "postgres".to_string(),
+            host: "0.0.0.0".to_string(),
+            port: 3000,
+            listen: Vec::new(),
+            listeners: BTreeMap::new(),
+            workers: 1,
+            static_dir: "public".to_string(),
+            body_limit: 2 * 1024 * 1024,
+            request_timeout: 30,
+            header_read_timeout: 10,
+            shutdown_timeout: 30,
+            tls: None,
+        }
+    }
+}
+
+impl Default for Tls {
+    fn default() -> Self {
+        Self {
+            cert: PathBuf::new(),
+            key: PathBuf::new(),
+            reload_interval: 60,
+            http2: true,
+            redirect_port: None,
         }
     }
 }
 
+impl Default for Log {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+            format: LogFormat::default(),
+        }
+    }
+}
+
+impl App {
+    pub fn is_production(&self) -> bool {
+        self.environment == "production"
+    }
+}
+
+impl Server {
+    pub fn to_address(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+}
+
+impl Default for Database {
+    fn default() -> Self {
+        Self::for_driver(Driver::default())
+    }
+}
+
 impl Database {
     pub fn new() -> Self {
         Self::default()
     }
 
+    /// The defaults of `driver`: its usual port and administrator account,
+    /// and for `sqlite` a database file in the working directory.
+    pub fn for_driver(driver: Driver) -> Self {
+        let (name, port, username, password) = match driver {
+            Driver::Postgres => ("jaya", 5432, "postgres", "postgres"),
+            Driver::Mysql => ("jaya", 3306, "root", ""),
+            Driver::Sqlite => ("jaya.db", 0, "", ""),
+        };
+        Self {
+            driver,
+            name: name.to_string(),
+            host: "localhost".to_string(),
+            port,
+            username: username.to_string(),
+            password: Secret::from(password),
+            password_file: None,
+            url: None,
+            max_connections: 10,
+            min_connections: 0,
+            acquire_timeout: 10,
+            idle_timeout: 60,
+            max_lifetime: 30 * 60,
+            statement_timeout: 0,
+            log_statements: LogLevel::default(),
+            slow_query_threshold: 1000,
+            n_plus_one_threshold: 5,
+            connect_retries: 5,
+            connect_retry_delay: 1,
+            ssl_mode: None,
+            application_name: None,
+            migrations: PathBuf::from("database/migrations"),
+            seeds: PathBuf::from("database/seeds"),
+            replicas: Vec::new(),
+        }
+    }
+
+    /// The connection URL, including the password. Never log it.
     pub fn to_database_url(&self) -> String {
+        if let Some(url) = &self.url {
+            return url.expose().to_string();
+        }
+
+        if self.driver == Driver::Sqlite {
+            return format!("sqlite://{}", self.name);
+        }
+
         format!(
-            "postgres://{}:{}@{}:{}/{}",
-            self.username, self.password, self.host, self.port, self.name
+            "{}{}:{}@{}:{}/{}",
+            self.driver.schemes()[0],
+            utf8_percent_encode(&self.username, URL_COMPONENT),
+            utf8_percent_encode(self.password.expose(), URL_COMPONENT),
+            self.host,
+            self.port,
+            utf8_percent_encode(&self.name, URL_COMPONENT)
         )
     }
 
+    /// The libpq `key=value` connection string of the `postgres` driver,
+    /// including the password. Never log it.
     pub fn to_connection_string(&self) -> String {
         format!(
             "host={} port={} user={} password={} dbname={}",
-            self.host, self.port, self.username, self.password, self.name
+            quote(&self.host),
+            self.port,
+            quote(&self.username),
+            quote(self.password.expose()),
+            quote(&self.name)
         )
     }
+
+    /// The settings to connect to `replica`: those of the primary with the
+    /// address of the replica.
+    pub fn replica(&self, replica: &Replica) -> Database {
+        let mut config = Database {
+            url: replica.url.clone(),
+            replicas: Vec::new(),
+            ..self.clone()
+        };
+        if let Some(host) = &replica.host {
+            config.host = host.clone();
+        }
+        if let Some(port) = replica.port {
+            config.port = port;
+        }
+        config
+    }
+
+    /// Replaces the password with the content of `password_file`, if set.
+    pub fn resolve_password_file(&mut self) -> Result<(), Error> {
+        if let Some(path) = &self.password_file {
+            let password = std::fs::read_to_string(path).map_err(|source| Error::Io {
+                path: path.clone(),
+                source,
+            })?;
+            self.password = Secret::new(password.trim_end_matches(['\r', '\n']));
+        }
+        Ok(())
+    }
+}
+
+/// Everything but the RFC 3986 unreserved characters.
+const URL_COMPONENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+fn quote(value: &str) -> String {
+    if !value.is_empty() && !value.contains([' ', '\'', '\\']) {
+        return value.to_string();
+    }
+    format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'"))
 }
 
 impl Config {
@@ -59,8 +444,51 @@ impl Config {
         Self::default()
     }
 
-    pub fn load_config_file(_path: &str) -> Self {
-        Self::default()
+    /// Loads the config with the default layering, see [`Loader`].
+    pub fn load() -> Result<Self, Error> {
+        Loader::new().load()
+    }
+
+    /// Loads the config from a TOML, YAML or JSON file, picked by extension.
+    /// Keys missing from the file keep their default values.
+    pub fn load_config_file(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let (format, text) = file::read(path)?;
+        let mut config: Config = file::parse(path, format, &text)?;
+        config.raw = Arc::new(file::parse(path, format, &text)?);
+        config.apply_driver_defaults();
+        config.database.resolve_password_file()?;
+        Ok(config)
+    }
+
+    pub(crate) fn set_raw(&mut self, raw: Value) {
+        self.raw = Arc::new(raw);
+    }
+
+    /// Whether `key` was given by a file or environment variable, as
+    /// opposed to left at its default.
+    pub(crate) fn is_set(&self, key: &str) -> bool {
+        key.split('.')
+            .try_fold(&*self.raw, |value, key| value.get(key))
+            .is_some_and(|value| !value.is_null())
+    }
+
+    /// Replaces the connection settings left unset, which default to those
+    /// of `postgres`, with the defaults of the configured driver.
+    pub(crate) fn apply_driver_defaults(&mut self) {
+        let defaults = Database::for_driver(self.database.driver);
+        if !self.is_set("database.name") {
+            self.database.name = defaults.name;
+        }
+        if !self.is_set("database.port") {
+            self.database.port = defaults.port;
+        }
+        if !self.is_set("database.username") {
+            self.database.username = defaults.username;
+        }
+        if !self.is_set("database.password") {
+            self.database.password = defaults.password;
+        }
     }
 }
 
@@ -0,0 +1,205 @@
+use std::path::{Path, PathBuf};
+
+use serde_json::{json, Value};
+
+use crate::{
+    file,
+    value::{self, Lenient},
+    Config, Error,
+};
+
+pub const DEFAULT_ENV_PREFIX: &str = "JAYA";
+
+/// Builds a [`Config`] out of several layers, each overriding the previous:
+///
+/// 1. the built-in defaults,
+/// 2. the base file, e.g. `config/app.toml`,
+/// 3. the environment file next to it, e.g. `config/production.toml`,
+/// 4. `JAYA_`-prefixed environment variables, where `__` separates nested
+///    keys (`JAYA_DATABASE__PASSWORD` sets `database.password`) and commas
+///    separate list items (`JAYA_SERVER__LISTEN=0.0.0.0:80,unix:/run/a.sock`),
+/// 5. `DATABASE_URL`, which sets `database.url`.
+///
+/// The environment is taken from `JAYA_ENV` unless set explicitly, and
+/// defaults to `development` in debug builds and `production` otherwise.
+#[derive(Clone)]
+pub struct Loader {
+    file: Option<PathBuf>,
+    environment: Option<String>,
+    env_prefix: Option<String>,
+}
+
+impl Default for Loader {
+    fn default() -> Self {
+        Self {
+            file: None,
+            environment: None,
+            env_prefix: Some(DEFAULT_ENV_PREFIX.to_string()),
+        }
+    }
+}
+
+impl Loader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_file(path: impl AsRef<Path>) -> Self {
+        Loader {
+            file: Some(path.as_ref().into()),
+            ..Default::default()
+        }
+    }
+
+    pub fn file(mut self, path: impl AsRef<Path>) -> Self {
+        self.file = Some(path.as_ref().into());
+        self
+    }
+
+    pub fn environment(mut self, environment: &str) -> Self {
+        self.environment = Some(environment.to_string());
+        self
+    }
+
+    /// Sets the prefix of overriding environment variables, or disables
+    /// them entirely with `None`.
+    pub fn env_prefix(mut self, prefix: Option<&str>) -> Self {
+        self.env_prefix = prefix.map(str::to_string);
+        self
+    }
+
+    pub fn set_file(&mut self, path: impl AsRef<Path>) -> &mut Self {
+        self.file = Some(path.as_ref().into());
+        self
+    }
+
+    pub fn set_environment(&mut self, environment: &str) -> &mut Self {
+        self.environment = Some(environment.to_string());
+        self
+    }
+
+    pub fn set_env_prefix(&mut self, prefix: Option<&str>) -> &mut Self {
+        self.env_prefix = prefix.map(str::to_string);
+        self
+    }
+
+    pub fn get_file(&self) -> Option<&Path> {
+        self.file.as_deref()
+    }
+
+    pub fn get_environment(&self) -> String {
+        if let Some(environment) = &self.environment {
+            return environment.clone();
+        }
+
+        let var = match &self.env_prefix {
+            Some(prefix) => format!("{}_ENV", prefix),
+            None => format!("{}_ENV", DEFAULT_ENV_PREFIX),
+        };
+        match std::env::var(var) {
+            Ok(environment) if !environment.is_empty() => environment,
+            _ if cfg!(debug_assertions) => "development".to_string(),
+            _ => "production".to_string(),
+        }
+    }
+
+    /// The environment specific file: the base file's sibling named after
+    /// the environment, with the same extension.
+    pub fn environment_file(&self) -> Option<PathBuf> {
+        let base = self.file.as_ref()?;
+        let mut path = base.with_file_name(self.get_environment());
+        if let Some(extension) = base.extension() {
+            path.set_extension(extension);
+        }
+        Some(path).filter(|path| path != base)
+    }
+
+    pub fn load(&self) -> Result<Config, Error> {
+        let mut tree = json!({ "app": { "environment": self.get_environment() } });
+
+        if let Some(path) = &self.file {
+            value::merge(&mut tree, read_layer(path)?);
+        }
+
+        if let Some(path) = self.environment_file() {
+            if path.is_file() {
+                value::merge(&mut tree, read_layer(&path)?);
+            }
+        }
+
+        let overrides = self.env_overrides();
+        if let Value::Object(table) = &mut tree {
+            for (_, path, value) in &overrides {
+                value::insert(table, path, Value::String(value.clone()));
+            }
+        }
+
+        let mut config: Config =
+            serde_path_to_error::deserialize(Lenient(tree.clone())).map_err(|err| {
+                let key = err.path().to_string();
+                let message = err.into_inner().to_string();
+                let from_env = overrides.iter().rev().find(|(_, path, _)| {
+                    let path = path.join(".");
+                    key == path || key.starts_with(&format!("{}.", path))
+                });
+                match from_env {
+                    Some((var, _, _)) => Error::Env {
+                        var: var.clone(),
+                        message,
+                    },
+                    None => Error::Invalid {
+                        key: Some(key).filter(|key| key != "."),
+                        message,
+                    },
+                }
+            })?;
+        config.set_raw(tree);
+        config.apply_driver_defaults();
+        config.database.resolve_password_file()?;
+        Ok(config)
+    }
+
+    /// Collects `(variable, key path, value)` for every overriding variable.
+    fn env_overrides(&self) -> Vec<(String, Vec<String>, String)> {
+        let mut overrides = Vec::new();
+
+        if let Some(prefix) = &self.env_prefix {
+            let prefix = format!("{}_", prefix);
+            let environment_var = format!("{}ENV", prefix);
+            let mut vars: Vec<(String, String)> = std::env::vars()
+                .filter(|(var, _)| var.starts_with(&prefix) && *var != environment_var)
+                .collect();
+            vars.sort();
+
+            for (var, value) in vars {
+                let path: Vec<String> = var[prefix.len()..]
+                    .split("__")
+                    .map(|key| key.to_lowercase())
+                    .collect();
+                if path.iter().any(|key| key.is_empty()) {
+                    continue;
+                }
+                overrides.push((var, path, value));
+            }
+        }
+
+        if let Ok(url) = std::env::var("DATABASE_URL") {
+            overrides.push((
+                "DATABASE_URL".to_string(),
+                vec!["database".to_string(), "url".to_string()],
+                url,
+            ));
+        }
+
+        overrides
+    }
+}
+
+/// Reads one file layer. The file is deserialized into [`Config`] on its
+/// own first, so a malformed key is reported with its file and line rather
+/// than after merging.
+fn read_layer(path: &Path) -> Result<Value, Error> {
+    let (format, text) = file::read(path)?;
+    file::parse::<Config>(path, format, &text)?;
+    file::parse(path, format, &text)
+}
@@ -0,0 +1,51 @@
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+
+use crate::{value::Lenient, Config, Error};
+
+/// An application defined config section, read from the top-level key
+/// [`Section::NAME`] of the same files and environment variables as the
+/// built-in sections.
+///
+/// ```ignore
+/// #[derive(Deserialize)]
+/// struct MailConfig {
+///     sender: String,
+/// }
+///
+/// impl Section for MailConfig {
+///     const NAME: &'static str = "mail";
+/// }
+/// ```
+pub trait Section: DeserializeOwned + Send + Sync + 'static {
+    const NAME: &'static str;
+}
+
+impl Config {
+    /// Deserializes the section at `key`, a dotted path such as `mail` or
+    /// `features.search`. A missing section reads as an empty table, so
+    /// sections whose fields all have defaults need not be present.
+    pub fn section<T: DeserializeOwned>(&self, key: &str) -> Result<T, Error> {
+        let value = key
+            .split('.')
+            .try_fold(&*self.raw, |value, key| value.get(key))
+            .cloned()
+            .unwrap_or_else(|| Value::Object(Map::new()));
+
+        serde_path_to_error::deserialize(Lenient(value)).map_err(|err| {
+            let path = err.path().to_string();
+            Error::Invalid {
+                key: Some(match path.as_str() {
+                    "." => key.to_string(),
+                    path => format!("{}.{}", key, path),
+                }),
+                message: err.into_inner().to_string(),
+            }
+        })
+    }
+
+    /// Deserializes the [`Section`] `T`.
+    pub fn get<T: Section>(&self) -> Result<T, Error> {
+        self.section(T::NAME)
+    }
+}
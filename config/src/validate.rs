@@ -0,0 +1,222 @@
+use std::path::Path;
+
+use crate::{Address, Config, Driver, Error, Secret};
+
+/// One problem found by [`Config::validate`].
+#[derive(Clone, Debug)]
+pub struct Issue {
+    pub key: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for Issue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "`{}`: {}", self.key, self.message)
+    }
+}
+
+struct Issues(Vec<Issue>);
+
+impl Issues {
+    fn check(&mut self, ok: bool, key: &str, message: &str) {
+        if !ok {
+            self.0.push(Issue {
+                key: key.to_string(),
+                message: message.to_string(),
+            });
+        }
+    }
+
+    fn into_result(self) -> Result<(), Error> {
+        if self.0.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Validation(self.0))
+        }
+    }
+}
+
+impl Config {
+    /// Checks the values serde cannot, reporting every problem at once.
+    pub fn validate(&self) -> Result<(), Error> {
+        let mut issues = Issues(Vec::new());
+        self.check_app(&mut issues);
+        self.check_server(&mut issues);
+        self.check_database(&mut issues);
+        issues.check(!self.log.level.is_empty(), "log.level", "must not be empty");
+        issues.into_result()
+    }
+
+    /// Same as [`Config::validate`], limited to the `database` section, for
+    /// the commands that only connect to the database, such as migrations.
+    /// They run fine without the static files or TLS certificates of the
+    /// server.
+    pub fn validate_database(&self) -> Result<(), Error> {
+        let mut issues = Issues(Vec::new());
+        self.check_database(&mut issues);
+        issues.into_result()
+    }
+
+    fn check_app(&self, issues: &mut Issues) {
+        let app = &self.app;
+
+        issues.check(!app.name.is_empty(), "app.name", "must not be empty");
+        issues.check(
+            app.base_url.starts_with("http://") || app.base_url.starts_with("https://"),
+            "app.base_url",
+            "must start with http:// or https://",
+        );
+    }
+
+    fn check_server(&self, issues: &mut Issues) {
+        let server = &self.server;
+
+        issues.check(!server.host.is_empty(), "server.host", "must not be empty");
+        issues.check(
+            server.port != 0,
+            "server.port",
+            "must be between 1 and 65535",
+        );
+        issues.check(
+            Path::new(&server.static_dir).is_dir(),
+            "server.static_dir",
+            &format!("`{}` is not a directory", server.static_dir),
+        );
+        issues.check(server.body_limit > 0, "server.body_limit", "must not be 0");
+        for (name, listener) in &server.listeners {
+            issues.check(
+                !listener.listen.is_empty(),
+                &format!("server.listeners.{}.listen", name),
+                "must list at least one address",
+            );
+        }
+        if let Some(tls) = &server.tls {
+            for (key, path) in [("server.tls.cert", &tls.cert), ("server.tls.key", &tls.key)] {
+                let set = !path.as_os_str().is_empty();
+                issues.check(set, key, "must be set when `server.tls` is");
+                if set {
+                    issues.check(
+                        path.is_file(),
+                        key,
+                        &format!("`{}` is not a file", path.display()),
+                    );
+                }
+            }
+            if let Some(port) = tls.redirect_port {
+                let app = match server.listen.is_empty() {
+                    true => vec![Address::tcp(&server.host, server.port)],
+                    false => server.listen.clone(),
+                };
+                let taken = app
+                    .iter()
+                    .chain(server.listeners.values().flat_map(|l| &l.listen))
+                    .any(|address| address.port() == Some(port));
+                issues.check(
+                    !taken,
+                    "server.tls.redirect_port",
+                    "must differ from the ports of the other addresses",
+                );
+                // Systemd may pass TCP sockets, which are only known then.
+                issues.check(
+                    !app.iter()
+                        .all(|address| matches!(address, Address::Unix(_))),
+                    "server.tls.redirect_port",
+                    "needs a TCP address in `server.listen` to redirect to",
+                );
+            }
+        }
+    }
+
+    fn check_database(&self, issues: &mut Issues) {
+        let database = &self.database;
+
+        let schemes = database.driver.schemes();
+        let scheme_message = format!(
+            "must start with {} for the `{}` driver",
+            schemes.join(" or "),
+            database.driver
+        );
+        let has_scheme = |url: &Secret| schemes.iter().any(|s| url.expose().starts_with(s));
+
+        match &database.url {
+            Some(url) => issues.check(has_scheme(url), "database.url", &scheme_message),
+            None if database.driver == Driver::Sqlite => issues.check(
+                !database.name.is_empty(),
+                "database.name",
+                "must be the path of the database file",
+            ),
+            None => {
+                issues.check(
+                    !database.name.is_empty(),
+                    "database.name",
+                    "must not be empty",
+                );
+                issues.check(
+                    !database.host.is_empty(),
+                    "database.host",
+                    "must not be empty",
+                );
+                issues.check(
+                    database.port != 0,
+                    "database.port",
+                    "must be between 1 and 65535",
+                );
+                issues.check(
+                    !database.username.is_empty(),
+                    "database.username",
+                    "must not be empty",
+                );
+            }
+        }
+        for (i, replica) in database.replicas.iter().enumerate() {
+            let key = format!("database.replicas.{}", i);
+            match &replica.url {
+                Some(url) => {
+                    issues.check(has_scheme(url), &format!("{}.url", key), &scheme_message)
+                }
+                None => {
+                    issues.check(
+                        database.url.is_none(),
+                        &format!("{}.url", key),
+                        "must be set when `database.url` is",
+                    );
+                    issues.check(
+                        replica.host.is_some() || replica.port.is_some(),
+                        &key,
+                        "must set `url`, `host` or `port`",
+                    );
+                    issues.check(
+                        !replica.host.as_ref().is_some_and(|host| host.is_empty()),
+                        &format!("{}.host", key),
+                        "must not be empty",
+                    );
+                }
+            }
+        }
+        issues.check(
+            !(self.is_set("database.password") && self.is_set("database.password_file")),
+            "database.password_file",
+            "cannot be used together with `database.password`",
+        );
+        issues.check(
+            database.max_connections > 0,
+            "database.max_connections",
+            "must not be 0",
+        );
+        issues.check(
+            database.min_connections <= database.max_connections,
+            "database.min_connections",
+            "must not exceed `database.max_connections`",
+        );
+        issues.check(
+            database.acquire_timeout > 0,
+            "database.acquire_timeout",
+            "must not be 0",
+        );
+        issues.check(
+            database.connect_retries == 0 || database.connect_retry_delay > 0,
+            "database.connect_retry_delay",
+            "must not be 0",
+        );
+    }
+}
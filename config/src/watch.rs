@@ -0,0 +1,204 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher as _};
+use tokio::sync::{mpsc, watch};
+
+use crate::{Config, Error, Loader};
+
+/// A check a reloaded config must pass before it is published.
+pub type Check = fn(&Config) -> Result<(), Error>;
+
+/// How long to wait for more file events before reloading, since editors
+/// and deploy tools tend to touch a file several times when saving it.
+const DEBOUNCE: Duration = Duration::from_millis(250);
+
+/// Re-runs a [`Loader`] whenever one of its files changes or the process
+/// receives `SIGHUP`, and publishes every config that loads, validates and
+/// passes its checks. A config that fails keeps the previous one in place.
+pub struct Watcher {
+    loader: Loader,
+    checks: Vec<Check>,
+}
+
+impl Watcher {
+    pub fn new(loader: Loader) -> Self {
+        Self {
+            loader,
+            checks: Vec::new(),
+        }
+    }
+
+    pub fn check(mut self, check: Check) -> Self {
+        self.checks.push(check);
+        self
+    }
+
+    pub fn set_check(&mut self, check: Check) -> &mut Self {
+        self.checks.push(check);
+        self
+    }
+
+    /// Starts watching on the current Tokio runtime, publishing `config`
+    /// until the first reload.
+    pub fn spawn(self, config: Config) -> Result<watch::Receiver<Arc<Config>>, Error> {
+        let (sender, receiver) = watch::channel(Arc::new(config));
+        let (trigger, mut triggered) = mpsc::unbounded_channel();
+
+        let files: Vec<PathBuf> = self
+            .loader
+            .get_file()
+            .map(Path::to_path_buf)
+            .into_iter()
+            .chain(self.loader.environment_file())
+            .collect();
+        let watcher = if files.is_empty() {
+            None
+        } else {
+            Some(watch_files(&files, trigger.clone())?)
+        };
+
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+
+            let mut hangup =
+                signal(SignalKind::hangup()).map_err(|e| Error::Watch(notify::Error::io(e)))?;
+            let trigger = trigger.clone();
+            tokio::spawn(async move {
+                while hangup.recv().await.is_some() {
+                    if trigger.send(()).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+
+        tokio::spawn(async move {
+            // Dropping the watcher would stop the file notifications.
+            let _watcher = watcher;
+
+            while triggered.recv().await.is_some() {
+                tokio::time::sleep(DEBOUNCE).await;
+                while triggered.try_recv().is_ok() {}
+
+                let current = sender.borrow().clone();
+                if let Some(config) = self.reload(&current) {
+                    sender.send_replace(Arc::new(config));
+                }
+            }
+        });
+
+        Ok(receiver)
+    }
+
+    fn reload(&self, current: &Config) -> Option<Config> {
+        let config = match self.loader.load() {
+            Ok(config) => config,
+            Err(e) => {
+                tracing::error!("Failed to reload config, keeping the current one: {}", e);
+                return None;
+            }
+        };
+
+        for check in [Config::validate as Check].iter().chain(&self.checks) {
+            if let Err(e) = check(&config) {
+                tracing::error!("Reloaded config is invalid, keeping the current one: {}", e);
+                return None;
+            }
+        }
+
+        for key in current.restart_required(&config) {
+            tracing::warn!("Config `{}` changed, restart the server to apply it", key);
+        }
+        tracing::info!("Config reloaded");
+
+        Some(config)
+    }
+}
+
+/// Watches the directories of `files` rather than the files themselves, so
+/// files that are replaced instead of written in place (editors, Kubernetes
+/// config maps) keep being noticed.
+fn watch_files(
+    files: &[PathBuf],
+    trigger: mpsc::UnboundedSender<()>,
+) -> Result<RecommendedWatcher, Error> {
+    let names: Vec<_> = files
+        .iter()
+        .filter_map(|file| file.file_name().map(|name| name.to_os_string()))
+        .collect();
+
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+        let Ok(event) = event else {
+            return;
+        };
+        let relevant = event.paths.iter().any(|path| {
+            path.file_name()
+                .is_some_and(|name| names.iter().any(|n| n == name))
+        });
+        if relevant && !event.kind.is_access() {
+            let _ = trigger.send(());
+        }
+    })
+    .map_err(Error::Watch)?;
+
+    for file in files {
+        let dir = match file.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        watcher
+            .watch(dir, RecursiveMode::NonRecursive)
+            .map_err(Error::Watch)?;
+    }
+
+    Ok(watcher)
+}
+
+macro_rules! changed_fields {
+    ($keys:ident, $old:expr, $new:expr, $section:literal, [$($field:ident),*]) => {
+        $(
+            if $old.$field != $new.$field {
+                $keys.push(concat!($section, ".", stringify!($field)));
+            }
+        )*
+    };
+}
+
+impl Config {
+    /// The keys that differ between `self` and `other` but are only read
+    /// at startup, such as the bind address or the database pool.
+    pub fn restart_required(&self, other: &Config) -> Vec<&'static str> {
+        let mut keys = Vec::new();
+
+        changed_fields!(
+            keys,
+            self.server,
+            other.server,
+            "server",
+            [
+                host,
+                port,
+                listen,
+                listeners,
+                workers,
+                static_dir,
+                body_limit,
+                request_timeout,
+                header_read_timeout,
+                shutdown_timeout,
+                tls
+            ]
+        );
+        if self.database != other.database {
+            keys.push("database");
+        }
+        changed_fields!(keys, self.log, other.log, "log", [format]);
+
+        keys
+    }
+}
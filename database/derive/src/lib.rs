@@ -0,0 +1,314 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, LitStr, Type};
+
+/// Derives `database::Model`, see its documentation for the attributes.
+#[proc_macro_derive(Model, attributes(model))]
+pub fn derive_model(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    model(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+struct Column {
+    ident: syn::Ident,
+    ty: Type,
+    name: String,
+    primary_key: bool,
+    insertable: bool,
+    sortable: bool,
+    filterable: bool,
+    default: Option<String>,
+    on_update: Option<String>,
+    /// The `deleted_at` column of `soft_delete`, only written by deleting
+    /// and restoring.
+    deleted_at: bool,
+}
+
+impl Column {
+    /// Whether insert binds the value of the field, rather than leaving
+    /// the column to the database or to a `default` expression.
+    fn binds_on_insert(&self) -> bool {
+        self.default.is_none() && !self.deleted_at && (!self.primary_key || self.insertable)
+    }
+
+    /// Whether update sets the column at all, and binds the value of the
+    /// field rather than an `on_update` expression.
+    fn binds_on_update(&self) -> bool {
+        !self.primary_key && !self.deleted_at && self.default.is_none() && self.on_update.is_none()
+    }
+}
+
+fn model(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let ident = &input.ident;
+
+    let mut table = None;
+    let mut timestamps = false;
+    let mut soft_delete = false;
+    let mut audit = false;
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("model"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("table") {
+                table = Some(meta.value()?.parse::<LitStr>()?.value());
+            } else if meta.path.is_ident("timestamps") {
+                timestamps = true;
+            } else if meta.path.is_ident("soft_delete") {
+                soft_delete = true;
+            } else if meta.path.is_ident("audit") {
+                audit = true;
+            } else {
+                return Err(meta.error("expected `table`, `timestamps`, `soft_delete` or `audit`"));
+            }
+            Ok(())
+        })?;
+    }
+    let table = table.unwrap_or_else(|| default_table(&ident.to_string()));
+
+    let Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new_spanned(
+            ident,
+            "Model can only be derived for structs",
+        ));
+    };
+    let Fields::Named(fields) = &data.fields else {
+        return Err(syn::Error::new_spanned(
+            ident,
+            "Model can only be derived for structs with named fields",
+        ));
+    };
+
+    let mut columns = Vec::new();
+    for field in &fields.named {
+        let ident = field.ident.clone().expect("named field");
+        let mut column = Column {
+            name: ident.to_string(),
+            ident,
+            ty: field.ty.clone(),
+            primary_key: false,
+            insertable: false,
+            sortable: false,
+            filterable: false,
+            default: None,
+            on_update: None,
+            deleted_at: false,
+        };
+        for attr in field
+            .attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("model"))
+        {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("primary_key") {
+                    column.primary_key = true;
+                } else if meta.path.is_ident("insertable") {
+                    column.insertable = true;
+                } else if meta.path.is_ident("sortable") {
+                    column.sortable = true;
+                } else if meta.path.is_ident("filterable") {
+                    column.filterable = true;
+                } else if meta.path.is_ident("rename") {
+                    column.name = meta.value()?.parse::<LitStr>()?.value();
+                } else if meta.path.is_ident("default") {
+                    column.default = Some(meta.value()?.parse::<LitStr>()?.value());
+                } else if meta.path.is_ident("on_update") {
+                    column.on_update = Some(meta.value()?.parse::<LitStr>()?.value());
+                } else {
+                    return Err(meta.error(
+                        "expected `primary_key`, `insertable`, `sortable`, `filterable`, `rename`, \
+                         `default` or `on_update`",
+                    ));
+                }
+                Ok(())
+            })?;
+        }
+        columns.push(column);
+    }
+
+    if timestamps {
+        let created_at = column_named(&mut columns, ident, "timestamps", "created_at")?;
+        created_at
+            .default
+            .get_or_insert_with(|| "current_timestamp".to_string());
+        let updated_at = column_named(&mut columns, ident, "timestamps", "updated_at")?;
+        updated_at
+            .default
+            .get_or_insert_with(|| "current_timestamp".to_string());
+        updated_at
+            .on_update
+            .get_or_insert_with(|| "current_timestamp".to_string());
+    }
+    let soft_delete = if soft_delete {
+        let deleted_at = column_named(&mut columns, ident, "soft_delete", "deleted_at")?;
+        deleted_at.deleted_at = true;
+        quote!(::std::option::Option::Some("deleted_at"))
+    } else {
+        quote!(::std::option::Option::None)
+    };
+
+    let mut keys = columns.iter().filter(|column| column.primary_key);
+    let key = match (keys.next(), keys.next()) {
+        (Some(key), None) => key,
+        (None, _) => {
+            return Err(syn::Error::new_spanned(
+                ident,
+                "Model needs a field marked `#[model(primary_key)]`",
+            ))
+        }
+        (Some(_), Some(second)) => {
+            return Err(syn::Error::new_spanned(
+                &second.ident,
+                "Model supports a single `#[model(primary_key)]` field",
+            ))
+        }
+    };
+    let key_ident = &key.ident;
+    let key_ty = &key.ty;
+    let key_name = &key.name;
+
+    let column_defs = columns.iter().map(|column| {
+        let name = &column.name;
+        let primary_key = column.primary_key;
+        let sortable = column.sortable;
+        let filterable = column.filterable;
+        let insert = if column.binds_on_insert() {
+            quote!(::database::Value::Bind)
+        } else if let Some(sql) = &column.default {
+            quote!(::database::Value::Sql(#sql))
+        } else {
+            quote!(::database::Value::Skip)
+        };
+        let update = if column.binds_on_update() {
+            quote!(::database::Value::Bind)
+        } else if let (false, Some(sql)) = (column.primary_key, &column.on_update) {
+            quote!(::database::Value::Sql(#sql))
+        } else {
+            quote!(::database::Value::Skip)
+        };
+        quote! {
+            ::database::Column {
+                name: #name,
+                primary_key: #primary_key,
+                sortable: #sortable,
+                filterable: #filterable,
+                insert: #insert,
+                update: #update,
+            }
+        }
+    });
+    let insert_binds = columns
+        .iter()
+        .filter(|column| column.binds_on_insert())
+        .map(|column| &column.ident);
+    let update_binds = columns
+        .iter()
+        .filter(|column| column.binds_on_update())
+        .map(|column| &column.ident);
+
+    let to_audit_json = audit.then(|| {
+        quote! {
+            fn to_audit_json(&self) -> ::std::option::Option<::database::serde_json::Value> {
+                ::database::serde_json::to_value(self).ok()
+            }
+        }
+    });
+
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::database::Model for #ident #ty_generics #where_clause {
+            type Key = #key_ty;
+
+            const TABLE: &'static str = #table;
+            const PRIMARY_KEY: &'static str = #key_name;
+            const COLUMNS: &'static [::database::Column] = &[#(#column_defs),*];
+            const SOFT_DELETE: ::std::option::Option<&'static str> = #soft_delete;
+            const AUDIT: bool = #audit;
+
+            fn key(&self) -> Self::Key {
+                ::std::clone::Clone::clone(&self.#key_ident)
+            }
+
+            fn bind_insert<'q>(&'q self, args: &mut ::database::Args<'q>) {
+                #(::database::Arguments::add(args, &self.#insert_binds);)*
+            }
+
+            fn bind_update<'q>(&'q self, args: &mut ::database::Args<'q>) {
+                #(::database::Arguments::add(args, &self.#update_binds);)*
+            }
+
+            #to_audit_json
+        }
+    })
+}
+
+/// The column `name` that the struct attribute `feature` relies on.
+fn column_named<'a>(
+    columns: &'a mut [Column],
+    ident: &syn::Ident,
+    feature: &str,
+    name: &str,
+) -> syn::Result<&'a mut Column> {
+    columns
+        .iter_mut()
+        .find(|column| column.name == name)
+        .ok_or_else(|| {
+            syn::Error::new_spanned(ident, format!("`{}` needs a `{}` column", feature, name))
+        })
+}
+
+/// The table of a struct without a `table` attribute.
+fn default_table(name: &str) -> String {
+    format!("{}s", snake_case(name))
+}
+
+/// Starts a word at each capital letter, except within a run of capitals
+/// such as an acronym, whose last letter starts a new word only when a
+/// lowercase letter follows: `PostDB` is `post_db`, `HTMLPage` `html_page`.
+fn snake_case(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut snake = String::new();
+    for (i, &c) in chars.iter().enumerate() {
+        if c.is_uppercase() {
+            let after_lower = i > 0 && !chars[i - 1].is_uppercase() && chars[i - 1] != '_';
+            let ends_acronym = i > 0
+                && chars[i - 1].is_uppercase()
+                && chars.get(i + 1).is_some_and(|next| next.is_lowercase());
+            if after_lower || ends_acronym {
+                snake.push('_');
+            }
+            snake.extend(c.to_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+    snake
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snake_cases_struct_names() {
+        assert_eq!(snake_case("Post"), "post");
+        assert_eq!(snake_case("BlogPost"), "blog_post");
+        assert_eq!(snake_case("PostDB"), "post_db");
+        assert_eq!(snake_case("HTMLPage"), "html_page");
+        assert_eq!(snake_case("UserIDMap"), "user_id_map");
+        assert_eq!(snake_case("Post2FA"), "post2_fa");
+        assert_eq!(snake_case("Audit_Log"), "audit_log");
+    }
+
+    #[test]
+    fn pluralizes_default_tables() {
+        assert_eq!(default_table("Post"), "posts");
+        assert_eq!(default_table("PostDB"), "post_dbs");
+        assert_eq!(default_table("AuditLog"), "audit_logs");
+    }
+}
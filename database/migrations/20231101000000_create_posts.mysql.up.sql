@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS posts (
+    id BIGINT AUTO_INCREMENT PRIMARY KEY,
+    title TEXT,
+    body TEXT,
+    created_at TIMESTAMP NULL DEFAULT current_timestamp,
+    updated_at TIMESTAMP NULL DEFAULT current_timestamp
+);
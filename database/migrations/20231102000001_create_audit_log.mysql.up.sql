@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS audit_log (
+    id BIGINT AUTO_INCREMENT PRIMARY KEY,
+    table_name VARCHAR(255) NOT NULL,
+    row_key VARCHAR(255) NOT NULL,
+    action TEXT NOT NULL,
+    actor TEXT,
+    old_data JSON,
+    new_data JSON,
+    created_at TIMESTAMP NOT NULL DEFAULT current_timestamp,
+    INDEX audit_log_row (table_name, row_key)
+);
@@ -0,0 +1,103 @@
+use std::future::Future;
+
+use crate::{
+    driver::{placeholder, TEXT},
+    Connection, Model, DRIVER,
+};
+
+/// The table [`Model`]s deriving `audit` record their changes to, created by
+/// a migration of the application, here the one of Postgres:
+///
+/// ```sql
+/// CREATE TABLE audit_log (
+///     id BIGSERIAL PRIMARY KEY,
+///     table_name TEXT NOT NULL,
+///     row_key TEXT NOT NULL,
+///     action TEXT NOT NULL,
+///     actor TEXT,
+///     old_data JSONB,
+///     new_data JSONB,
+///     created_at TIMESTAMPTZ NOT NULL DEFAULT current_timestamp
+/// );
+/// ```
+///
+/// MySQL needs `BIGINT AUTO_INCREMENT`, `VARCHAR(255)` for the indexed
+/// `table_name` and `row_key`, `JSON` and `TIMESTAMP`, and SQLite
+/// `INTEGER PRIMARY KEY` for the id to be generated. The migrations of
+/// `database/migrations` create it for each driver.
+pub const AUDIT_TABLE: &str = "audit_log";
+
+tokio::task_local! {
+    static ACTOR: String;
+}
+
+/// Runs `f` with `actor`, such as the id of the signed in user, recorded as
+/// the author of the audited changes it makes. Changes made by a task `f`
+/// spawns are recorded without an actor, unless it is spawned with its own
+/// `acting_as`.
+pub async fn acting_as<F: Future>(actor: impl Into<String>, f: F) -> F::Output {
+    ACTOR.scope(actor.into(), f).await
+}
+
+/// The actor set by [`acting_as`], if any.
+pub fn current_actor() -> Option<String> {
+    ACTOR.try_with(|actor| actor.clone()).ok()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Insert,
+    Update,
+    Delete,
+    Restore,
+}
+
+impl Action {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Action::Insert => "insert",
+            Action::Update => "update",
+            Action::Delete => "delete",
+            Action::Restore => "restore",
+        }
+    }
+}
+
+/// Records a change of a row, from `old` to `new`.
+pub(crate) async fn record<M: Model>(
+    conn: &mut Connection,
+    action: Action,
+    old: Option<&M>,
+    new: Option<&M>,
+) -> Result<(), sqlx::Error> {
+    let Some(key) = new.or(old).map(|row| row.key()) else {
+        return Ok(());
+    };
+    let json = |n| match DRIVER {
+        config::Driver::Postgres => format!("CAST({} AS JSONB)", placeholder(n)),
+        _ => placeholder(n),
+    };
+    let sql = format!(
+        "INSERT INTO {} (table_name, row_key, action, actor, old_data, new_data) \
+         VALUES ({}, CAST({} AS {}), {}, {}, {}, {})",
+        AUDIT_TABLE,
+        placeholder(1),
+        placeholder(2),
+        TEXT,
+        placeholder(3),
+        placeholder(4),
+        json(5),
+        json(6)
+    );
+
+    sqlx::query(&sql)
+        .bind(M::TABLE)
+        .bind(key)
+        .bind(action.as_str())
+        .bind(current_actor())
+        .bind(old.and_then(M::to_audit_json).map(|json| json.to_string()))
+        .bind(new.and_then(M::to_audit_json).map(|json| json.to_string()))
+        .execute(conn)
+        .await?;
+    Ok(())
+}
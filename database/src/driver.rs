@@ -0,0 +1,42 @@
+//! The database the crate is built for, picked by cargo feature: `postgres`
+//! by default, or `mysql` or `sqlite` instead.
+
+#[cfg(all(feature = "mysql", feature = "sqlite"))]
+compile_error!("the `mysql` and `sqlite` features of `database` cannot be enabled together");
+
+#[cfg(not(any(feature = "mysql", feature = "sqlite")))]
+pub type Driver = sqlx::Postgres;
+#[cfg(feature = "mysql")]
+pub type Driver = sqlx::MySql;
+#[cfg(feature = "sqlite")]
+pub type Driver = sqlx::Sqlite;
+
+#[cfg(not(any(feature = "mysql", feature = "sqlite")))]
+pub const DRIVER: config::Driver = config::Driver::Postgres;
+#[cfg(feature = "mysql")]
+pub const DRIVER: config::Driver = config::Driver::Mysql;
+#[cfg(feature = "sqlite")]
+pub const DRIVER: config::Driver = config::Driver::Sqlite;
+
+pub type Connection = <Driver as sqlx::Database>::Connection;
+pub type Row = <Driver as sqlx::Database>::Row;
+pub type QueryResult = <Driver as sqlx::Database>::QueryResult;
+pub type Args<'q> = <Driver as sqlx::database::HasArguments<'q>>::Arguments;
+
+/// The `n`th bind parameter, counted from 1. MySQL only has positional
+/// `?`, so parameters must be bound in the order they appear there.
+pub(crate) fn placeholder(n: usize) -> String {
+    match DRIVER {
+        config::Driver::Mysql => "?".to_string(),
+        _ => format!("${}", n),
+    }
+}
+
+/// Whether `INSERT`, `UPDATE` and `DELETE` support `RETURNING`.
+pub(crate) const RETURNING: bool = !matches!(DRIVER, config::Driver::Mysql);
+
+/// The type to `CAST` a column to for comparing it as text.
+pub(crate) const TEXT: &str = match DRIVER {
+    config::Driver::Mysql => "CHAR",
+    _ => "TEXT",
+};
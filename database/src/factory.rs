@@ -0,0 +1,84 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{Connection, Model, Repository};
+
+/// Builds model instances for seeders and tests, from a function of a
+/// sequence number that counts up from 1 across every call.
+///
+/// ```ignore
+/// let posts = Factory::new(|n| PostDB {
+///     title: Some(format!("Post {n}")),
+///     ..Default::default()
+/// })
+/// .count(10)
+/// .create(&mut conn)
+/// .await?;
+/// ```
+type State<T> = Box<dyn Fn(&mut T, usize) + Send + Sync>;
+
+pub struct Factory<T> {
+    build: Box<dyn Fn(usize) -> T + Send + Sync>,
+    states: Vec<State<T>>,
+    count: usize,
+    sequence: AtomicUsize,
+}
+
+impl<T: Model> Factory<T> {
+    pub fn new(build: impl Fn(usize) -> T + Send + Sync + 'static) -> Self {
+        Factory {
+            build: Box::new(build),
+            states: Vec::new(),
+            count: 1,
+            sequence: AtomicUsize::new(1),
+        }
+    }
+
+    /// How many instances [`Factory::make`] and [`Factory::create`] build.
+    pub fn count(mut self, count: usize) -> Self {
+        self.count = count;
+        self
+    }
+
+    pub fn set_count(&mut self, count: usize) -> &mut Self {
+        self.count = count;
+        self
+    }
+
+    /// Changes every built instance, after the ones added before it.
+    pub fn state(mut self, state: impl Fn(&mut T, usize) + Send + Sync + 'static) -> Self {
+        self.states.push(Box::new(state));
+        self
+    }
+
+    pub fn set_state(
+        &mut self,
+        state: impl Fn(&mut T, usize) + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.states.push(Box::new(state));
+        self
+    }
+
+    /// Builds one instance without storing it.
+    pub fn make_one(&self) -> T {
+        let n = self.sequence.fetch_add(1, Ordering::Relaxed);
+        let mut model = (self.build)(n);
+        for state in &self.states {
+            state(&mut model, n);
+        }
+        model
+    }
+
+    /// Builds `count` instances without storing them.
+    pub fn make(&self) -> Vec<T> {
+        (0..self.count).map(|_| self.make_one()).collect()
+    }
+
+    /// Inserts `count` instances, returning them as stored.
+    pub async fn create(&self, conn: &mut Connection) -> Result<Vec<T>, sqlx::Error> {
+        let mut models = Vec::with_capacity(self.count);
+        for model in self.make() {
+            models.push(model.insert(&mut *conn).await?);
+        }
+        Ok(models)
+    }
+}
@@ -0,0 +1,132 @@
+use std::{
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+
+use config::Database;
+use serde::Serialize;
+
+use crate::{replica::Replicas, DB};
+
+/// Shortest and longest wait between two connection attempts on startup.
+const MIN_RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+/// How long [`DB::health`] waits for the database to answer.
+const PING_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Health {
+    /// Whether the primary answered a query.
+    pub reachable: bool,
+    pub latency_ms: Option<u64>,
+    pub error: Option<String>,
+    pub pool: PoolStatus,
+    pub replicas: ReplicaStatus,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PoolStatus {
+    pub size: u32,
+    pub idle: usize,
+    pub max: u32,
+    /// Share of the maximum connections in use, from 0 to 1.
+    pub saturation: f64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ReplicaStatus {
+    pub total: usize,
+    pub healthy: usize,
+}
+
+impl DB {
+    /// Connects like [`DB::connect`], retrying `database.connect_retries`
+    /// times with an exponential backoff, so a server starting alongside
+    /// its database waits for it instead of exiting.
+    pub async fn connect_with_retry(config: &Database) -> Result<Self, sqlx::Error> {
+        let mut delay =
+            Duration::from_secs(config.connect_retry_delay).clamp(MIN_RETRY_DELAY, MAX_RETRY_DELAY);
+        let mut attempt = 0;
+
+        loop {
+            match DB::connect(config).await {
+                Ok(db) => return Ok(db),
+                // Retrying will not fix a wrong setting.
+                Err(e @ sqlx::Error::Configuration(_)) => return Err(e),
+                Err(e) if attempt >= config.connect_retries => return Err(e),
+                Err(e) => {
+                    attempt += 1;
+                    tracing::warn!(
+                        "Failed to connect to the database ({}), retrying in {:?} ({}/{})",
+                        e,
+                        delay,
+                        attempt,
+                        config.connect_retries
+                    );
+                    tokio::time::sleep(delay).await;
+                    delay = delay.saturating_mul(2).min(MAX_RETRY_DELAY);
+                }
+            }
+        }
+    }
+
+    /// Runs a trivial query on the primary, returning how long it took.
+    pub async fn ping(&self) -> Result<Duration, sqlx::Error> {
+        let start = Instant::now();
+        sqlx::query("SELECT 1").execute(self.writer()).await?;
+        Ok(start.elapsed())
+    }
+
+    /// Pings the primary and the replicas, and reports the state of the
+    /// pools. Replicas that do not answer are taken out of rotation, as by
+    /// [`DB::check_replicas`].
+    pub async fn health(&self) -> Health {
+        // Taken first so the ping's own connection is not counted.
+        let pool = self.pool_status();
+        let (ping, ()) = tokio::join!(
+            tokio::time::timeout(PING_TIMEOUT, self.ping()),
+            self.replicas.probe()
+        );
+        let (reachable, latency_ms, error) = match ping {
+            Ok(Ok(latency)) => (true, Some(latency.as_millis() as u64), None),
+            Ok(Err(e)) => (false, None, Some(e.to_string())),
+            Err(_) => (false, None, Some("timed out".to_string())),
+        };
+
+        Health {
+            reachable,
+            latency_ms,
+            error,
+            pool,
+            replicas: self.replicas.status(),
+        }
+    }
+
+    /// The connections of the primary pool, without querying the database.
+    pub fn pool_status(&self) -> PoolStatus {
+        let pool = self.writer();
+        let size = pool.size();
+        let idle = pool.num_idle();
+        let max = pool.options().get_max_connections();
+
+        PoolStatus {
+            size,
+            idle,
+            max,
+            saturation: size.saturating_sub(idle as u32) as f64 / max.max(1) as f64,
+        }
+    }
+}
+
+impl Replicas {
+    fn status(&self) -> ReplicaStatus {
+        ReplicaStatus {
+            total: self.replicas.len(),
+            healthy: self
+                .replicas
+                .iter()
+                .filter(|replica| replica.healthy.load(Ordering::Relaxed))
+                .count(),
+        }
+    }
+}
@@ -1,38 +1,252 @@
-use std::time::Duration;
+mod audit;
+mod driver;
+mod factory;
+mod health;
+mod migrate;
+mod model;
+mod notify;
+mod page;
+mod query;
+mod replica;
+mod seed;
+mod stats;
+mod transaction;
 
-use sqlx::{postgres::PgPoolOptions, Pool, Postgres};
+use std::{str::FromStr, sync::Arc, time::Duration};
 
+use config::Database;
+use sqlx::{pool::PoolOptions, ConnectOptions, Pool};
+
+pub use crate::audit::{acting_as, current_actor, Action, AUDIT_TABLE};
+pub use crate::driver::{Args, Connection, Driver, QueryResult, Row, DRIVER};
+pub use crate::factory::Factory;
+pub use crate::health::{Health, PoolStatus, ReplicaStatus};
+pub use crate::migrate::{MigrateError, Migration, MigrationStatus, Migrator};
+pub use crate::model::{Column, Model, Repository, Trashed, Value};
+pub use crate::notify::{notify, Subscription};
+pub use crate::page::{
+    Links, Mode, Order, Page, PageError, PageRequest, Sort, DEFAULT_PER_PAGE, MAX_PER_PAGE,
+};
+pub use crate::query::Query;
+pub use crate::replica::{is_read_only, read_only};
+pub use crate::seed::{Fixture, SeedError, Seeder, Seeds};
+pub use crate::stats::{track, QueryLayer, QueryStats, QUERY_TARGET};
+pub use crate::transaction::{savepoint, BoxFuture, Transaction};
+pub use async_trait::async_trait;
+pub use database_derive::Model;
+#[doc(hidden)]
+pub use serde_json;
+pub use sqlx::Arguments;
+
+use crate::notify::Channels;
+use crate::replica::Replicas;
+
+/// The primary pool along with its read replicas, if any.
 #[derive(Clone)]
 pub struct DB {
-    pool: Pool<Postgres>,
+    pool: Pool<Driver>,
+    replicas: Arc<Replicas>,
+    channels: Arc<Channels>,
 }
 
 impl DB {
-    pub fn new(pool: Pool<Postgres>) -> Self {
-        DB { pool }
+    pub fn new(pool: Pool<Driver>) -> Self {
+        DB {
+            pool,
+            replicas: Arc::default(),
+            channels: Arc::default(),
+        }
     }
 
-    pub async fn connect(conn_str: &str) -> Result<Self, sqlx::Error> {
-        Ok(DB {
-            pool: PgPoolOptions::new()
-                .acquire_timeout(Duration::from_secs(10))
-                .idle_timeout(Some(Duration::from_secs(60)))
-                .connect(conn_str)
-                .await?,
-        })
+    /// Connects to the primary, and lazily to the replicas so one being
+    /// down does not prevent starting.
+    pub async fn connect(config: &Database) -> Result<Self, sqlx::Error> {
+        if config.driver != DRIVER {
+            return Err(sqlx::Error::Configuration(
+                format!(
+                    "`database.driver` is `{}` but the `database` crate is built for `{}`",
+                    config.driver, DRIVER
+                )
+                .into(),
+            ));
+        }
+
+        let pool = pool_options(config)
+            .connect_with(log_options(connect_options(config)?, config))
+            .await?;
+        let replicas = config
+            .replicas
+            .iter()
+            .map(|replica| {
+                let config = config.replica(replica);
+                Ok(pool_options(&config)
+                    .connect_lazy_with(log_options(connect_options(&config)?, &config)))
+            })
+            .collect::<Result<Vec<_>, sqlx::Error>>()?;
+
+        let db = DB::new(pool).replicas(replicas);
+        db.replicas.probe().await;
+        db.check_replicas();
+        Ok(db)
     }
 
-    pub fn pool(mut self, pool: Pool<Postgres>) -> Self {
+    pub fn pool(mut self, pool: Pool<Driver>) -> Self {
         self.pool = pool;
         self
     }
 
-    pub fn set_pool(&mut self, pool: Pool<Postgres>) -> &mut Self {
+    pub fn set_pool(&mut self, pool: Pool<Driver>) -> &mut Self {
         self.pool = pool;
         self
     }
 
-    pub fn get_pool(&self) -> &Pool<Postgres> {
-        &self.pool
+    pub fn replicas(mut self, replicas: Vec<Pool<Driver>>) -> Self {
+        self.replicas = Arc::new(Replicas::new(replicas));
+        self
+    }
+
+    pub fn set_replicas(&mut self, replicas: Vec<Pool<Driver>>) -> &mut Self {
+        self.replicas = Arc::new(Replicas::new(replicas));
+        self
+    }
+
+    /// The primary pool, or a replica inside of [`read_only`].
+    pub fn get_pool(&self) -> &Pool<Driver> {
+        if replica::is_read_only() {
+            self.reader()
+        } else {
+            &self.pool
+        }
+    }
+}
+
+fn pool_options(config: &Database) -> PoolOptions<Driver> {
+    let seconds = |secs: u64| Some(secs).filter(|secs| *secs > 0).map(Duration::from_secs);
+
+    let options = PoolOptions::new()
+        .max_connections(config.max_connections)
+        .min_connections(config.min_connections)
+        .acquire_timeout(Duration::from_secs(config.acquire_timeout))
+        .idle_timeout(seconds(config.idle_timeout))
+        .max_lifetime(seconds(config.max_lifetime));
+
+    // MySQL has no connect option for it, so it is set on every connection.
+    #[cfg(feature = "mysql")]
+    let options = match config.statement_timeout {
+        0 => options,
+        secs => options.after_connect(move |conn, _| {
+            Box::pin(async move {
+                let sql = format!("SET SESSION max_execution_time = {}", secs * 1000);
+                sqlx::Executor::execute(conn, sql.as_str()).await?;
+                Ok(())
+            })
+        }),
+    };
+
+    options
+}
+
+/// Logs statements as `database.log_statements` says, and those slower
+/// than `database.slow_query_threshold` at `warn`.
+fn log_options<O: ConnectOptions>(options: O, config: &Database) -> O {
+    use config::LogLevel;
+    use log::LevelFilter;
+
+    let level = match config.log_statements {
+        LogLevel::Off => LevelFilter::Off,
+        LogLevel::Error => LevelFilter::Error,
+        LogLevel::Warn => LevelFilter::Warn,
+        LogLevel::Info => LevelFilter::Info,
+        LogLevel::Debug => LevelFilter::Debug,
+        LogLevel::Trace => LevelFilter::Trace,
+    };
+    let options = options.log_statements(level);
+    match config.slow_query_threshold {
+        // No statement takes this long, so none is slow.
+        0 => options.log_slow_statements(LevelFilter::Off, Duration::MAX),
+        ms => options.log_slow_statements(LevelFilter::Warn, Duration::from_millis(ms)),
+    }
+}
+
+#[cfg(not(any(feature = "mysql", feature = "sqlite")))]
+fn connect_options(config: &Database) -> Result<sqlx::postgres::PgConnectOptions, sqlx::Error> {
+    use config::SslMode;
+    use sqlx::postgres::{PgConnectOptions, PgSslMode};
+
+    let mut options = match &config.url {
+        Some(url) => PgConnectOptions::from_str(url.expose())?,
+        None => PgConnectOptions::new()
+            .host(&config.host)
+            .port(config.port)
+            .username(&config.username)
+            .password(config.password.expose())
+            .database(&config.name),
+    };
+
+    if let Some(ssl_mode) = config.ssl_mode {
+        options = options.ssl_mode(match ssl_mode {
+            SslMode::Disable => PgSslMode::Disable,
+            SslMode::Allow => PgSslMode::Allow,
+            SslMode::Prefer => PgSslMode::Prefer,
+            SslMode::Require => PgSslMode::Require,
+            SslMode::VerifyCa => PgSslMode::VerifyCa,
+            SslMode::VerifyFull => PgSslMode::VerifyFull,
+        });
+    }
+
+    if let Some(application_name) = &config.application_name {
+        options = options.application_name(application_name);
+    }
+
+    if config.statement_timeout > 0 {
+        options = options.options([(
+            "statement_timeout",
+            format!("{}s", config.statement_timeout),
+        )]);
+    }
+
+    Ok(options)
+}
+
+#[cfg(feature = "mysql")]
+fn connect_options(config: &Database) -> Result<sqlx::mysql::MySqlConnectOptions, sqlx::Error> {
+    use config::SslMode;
+    use sqlx::mysql::{MySqlConnectOptions, MySqlSslMode};
+
+    let mut options = match &config.url {
+        Some(url) => MySqlConnectOptions::from_str(url.expose())?,
+        None => MySqlConnectOptions::new()
+            .host(&config.host)
+            .port(config.port)
+            .username(&config.username)
+            .password(config.password.expose())
+            .database(&config.name),
+    };
+
+    if let Some(ssl_mode) = config.ssl_mode {
+        options = options.ssl_mode(match ssl_mode {
+            SslMode::Disable => MySqlSslMode::Disabled,
+            SslMode::Allow | SslMode::Prefer => MySqlSslMode::Preferred,
+            SslMode::Require => MySqlSslMode::Required,
+            SslMode::VerifyCa => MySqlSslMode::VerifyCa,
+            SslMode::VerifyFull => MySqlSslMode::VerifyIdentity,
+        });
     }
+
+    Ok(options)
+}
+
+#[cfg(feature = "sqlite")]
+fn connect_options(config: &Database) -> Result<sqlx::sqlite::SqliteConnectOptions, sqlx::Error> {
+    use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode};
+
+    let options = match &config.url {
+        Some(url) => SqliteConnectOptions::from_str(url.expose())?,
+        None => SqliteConnectOptions::new().filename(&config.name),
+    };
+
+    // WAL lets readers in other worker processes go on during a write.
+    Ok(options
+        .create_if_missing(true)
+        .journal_mode(SqliteJournalMode::Wal))
 }
@@ -0,0 +1,425 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use sqlx::{pool::PoolConnection, Connection, Executor};
+
+use crate::{driver::placeholder, Driver, DB, DRIVER};
+
+/// Key of the advisory lock held while migrating, so that only one of
+/// several workers starting at once applies the migrations. SQLite has no
+/// such lock, run its migrations from a single process.
+const LOCK_KEY: i64 = 0x6a61_7961_6d69_6772;
+const LOCK_NAME: &str = "jaya_migrations";
+
+const CREATE_TABLE: &str = match DRIVER {
+    config::Driver::Mysql => {
+        "CREATE TABLE IF NOT EXISTS _migrations (
+            version BIGINT PRIMARY KEY,
+            name TEXT NOT NULL,
+            checksum TEXT NOT NULL,
+            applied_at TIMESTAMP NOT NULL DEFAULT current_timestamp
+        )"
+    }
+    _ => {
+        "CREATE TABLE IF NOT EXISTS _migrations (
+            version BIGINT PRIMARY KEY,
+            name TEXT NOT NULL,
+            checksum TEXT NOT NULL,
+            applied_at TIMESTAMPTZ NOT NULL DEFAULT current_timestamp
+        )"
+    }
+};
+
+#[derive(Debug)]
+pub enum MigrateError {
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    InvalidName(PathBuf),
+    Duplicate(i64),
+    Modified {
+        version: i64,
+        name: String,
+    },
+    Irreversible {
+        version: i64,
+        name: String,
+    },
+    Database(sqlx::Error),
+}
+
+impl From<sqlx::Error> for MigrateError {
+    fn from(e: sqlx::Error) -> Self {
+        MigrateError::Database(e)
+    }
+}
+
+impl std::error::Error for MigrateError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MigrateError::Io { source, .. } => Some(source),
+            MigrateError::Database(source) => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for MigrateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            MigrateError::Io { path, source } => {
+                write!(f, "Failed to read {}: {}", path.display(), source)
+            }
+            MigrateError::InvalidName(path) => write!(
+                f,
+                "Invalid migration file name {}, expected <version>_<name>[.<driver>].up.sql or .down.sql",
+                path.display()
+            ),
+            MigrateError::Duplicate(version) => {
+                write!(f, "Migration version {} is used more than once", version)
+            }
+            MigrateError::Modified { version, name } => write!(
+                f,
+                "Migration {}_{} was modified after it was applied",
+                version, name
+            ),
+            MigrateError::Irreversible { version, name } => write!(
+                f,
+                "Migration {}_{} has no .down.sql file and cannot be reverted",
+                version, name
+            ),
+            MigrateError::Database(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Migration {
+    pub version: i64,
+    pub name: String,
+    pub up: String,
+    pub down: Option<String>,
+}
+
+impl Migration {
+    pub fn checksum(&self) -> String {
+        Sha256::digest(self.up.as_bytes())
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub name: String,
+    pub applied_at: Option<DateTime<Utc>>,
+}
+
+/// Runs the versioned SQL files of a directory, recording the applied ones
+/// in the `_migrations` table.
+///
+/// Files are named `<version>_<name>.up.sql`, with an optional
+/// `<version>_<name>.down.sql` to revert them, and run in version order.
+/// SQL that differs between databases goes in files named after the driver,
+/// `<version>_<name>.postgres.up.sql`, `.mysql.up.sql` or `.sqlite.up.sql`.
+/// Those of the driver the crate is built with replace the unnamed file,
+/// and those of the others are ignored.
+/// Each migration runs in its own transaction, though MySQL commits DDL
+/// statements such as `CREATE TABLE` right away.
+#[derive(Clone, Debug, Default)]
+pub struct Migrator {
+    migrations: Vec<Migration>,
+}
+
+impl Migrator {
+    pub fn new(migrations: Vec<Migration>) -> Self {
+        let mut migrator = Migrator { migrations };
+        migrator.migrations.sort_by_key(|m| m.version);
+        migrator
+    }
+
+    pub fn from_dir(dir: impl AsRef<Path>) -> Result<Self, MigrateError> {
+        let dir = dir.as_ref();
+        let io = |source| MigrateError::Io {
+            path: dir.into(),
+            source,
+        };
+
+        let mut migrations: HashMap<i64, Migration> = HashMap::new();
+        // The files of the driver, which the unnamed ones do not replace.
+        let mut for_driver: HashSet<(i64, bool)> = HashSet::new();
+        for entry in std::fs::read_dir(dir).map_err(io)? {
+            let path = entry.map_err(io)?.path();
+            let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            let (stem, up) = if let Some(stem) = file_name.strip_suffix(".up.sql") {
+                (stem, true)
+            } else if let Some(stem) = file_name.strip_suffix(".down.sql") {
+                (stem, false)
+            } else {
+                continue;
+            };
+            let (stem, driver) = match stem.rsplit_once('.') {
+                Some((stem, driver @ ("postgres" | "mysql" | "sqlite"))) => (stem, Some(driver)),
+                _ => (stem, None),
+            };
+            if driver.is_some_and(|driver| driver != DRIVER.to_string()) {
+                continue;
+            }
+
+            let (version, name) = stem
+                .split_once('_')
+                .and_then(|(version, name)| Some((version.parse::<i64>().ok()?, name)))
+                .ok_or_else(|| MigrateError::InvalidName(path.clone()))?;
+            let sql = std::fs::read_to_string(&path).map_err(|source| MigrateError::Io {
+                path: path.clone(),
+                source,
+            })?;
+
+            let migration = migrations.entry(version).or_insert_with(|| Migration {
+                version,
+                name: name.to_string(),
+                up: String::new(),
+                down: None,
+            });
+            if migration.name != name {
+                return Err(MigrateError::Duplicate(version));
+            }
+            if driver.is_some() {
+                for_driver.insert((version, up));
+            } else if for_driver.contains(&(version, up)) {
+                continue;
+            }
+            if up {
+                migration.up = sql;
+            } else {
+                migration.down = Some(sql);
+            }
+        }
+
+        Ok(Self::new(migrations.into_values().collect()))
+    }
+
+    pub fn migrations(&self) -> &[Migration] {
+        &self.migrations
+    }
+
+    /// Applies every pending migration, returning the applied ones.
+    pub async fn up(&self, db: &DB) -> Result<Vec<&Migration>, MigrateError> {
+        let mut lock = Lock::acquire(db).await?;
+        let result = self.up_locked(lock.conn()).await;
+        lock.release().await?;
+        result
+    }
+
+    /// Reverts the last `steps` applied migrations, returning the reverted
+    /// ones.
+    pub async fn down(&self, db: &DB, steps: usize) -> Result<Vec<&Migration>, MigrateError> {
+        let mut lock = Lock::acquire(db).await?;
+        let result = self.down_locked(lock.conn(), steps).await;
+        lock.release().await?;
+        result
+    }
+
+    /// Every known migration, applied or not, in version order.
+    pub async fn status(&self, db: &DB) -> Result<Vec<MigrationStatus>, MigrateError> {
+        let mut conn = db.writer().acquire().await?;
+        conn.execute(CREATE_TABLE).await?;
+        let applied = applied(&mut conn).await?;
+
+        let mut status: Vec<MigrationStatus> = self
+            .migrations
+            .iter()
+            .map(|migration| MigrationStatus {
+                version: migration.version,
+                name: migration.name.clone(),
+                applied_at: applied
+                    .iter()
+                    .find(|(version, ..)| *version == migration.version)
+                    .map(|(.., applied_at)| *applied_at),
+            })
+            .collect();
+        // Applied migrations whose files are gone still show up.
+        for (version, name, _, applied_at) in applied {
+            if !status.iter().any(|s| s.version == version) {
+                status.push(MigrationStatus {
+                    version,
+                    name,
+                    applied_at: Some(applied_at),
+                });
+            }
+        }
+        status.sort_by_key(|s| s.version);
+
+        Ok(status)
+    }
+
+    async fn up_locked(
+        &self,
+        conn: &mut PoolConnection<Driver>,
+    ) -> Result<Vec<&Migration>, MigrateError> {
+        let applied = applied(conn).await?;
+        let mut done = Vec::new();
+
+        for migration in &self.migrations {
+            match applied
+                .iter()
+                .find(|(version, ..)| *version == migration.version)
+            {
+                Some((_, _, checksum, _)) if *checksum != migration.checksum() => {
+                    return Err(MigrateError::Modified {
+                        version: migration.version,
+                        name: migration.name.clone(),
+                    });
+                }
+                Some(_) => continue,
+                None => {}
+            }
+
+            let mut tx = conn.begin().await?;
+            tx.execute(migration.up.as_str()).await?;
+            let sql = format!(
+                "INSERT INTO _migrations (version, name, checksum) VALUES ({}, {}, {})",
+                placeholder(1),
+                placeholder(2),
+                placeholder(3)
+            );
+            sqlx::query(&sql)
+                .bind(migration.version)
+                .bind(&migration.name)
+                .bind(migration.checksum())
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+
+            tracing::info!("Applied migration {}_{}", migration.version, migration.name);
+            done.push(migration);
+        }
+
+        Ok(done)
+    }
+
+    async fn down_locked(
+        &self,
+        conn: &mut PoolConnection<Driver>,
+        steps: usize,
+    ) -> Result<Vec<&Migration>, MigrateError> {
+        let applied = applied(conn).await?;
+        let mut done = Vec::new();
+
+        for (version, name, ..) in applied.iter().rev().take(steps) {
+            let migration = self
+                .migrations
+                .iter()
+                .find(|m| m.version == *version)
+                .ok_or_else(|| MigrateError::Irreversible {
+                    version: *version,
+                    name: name.clone(),
+                })?;
+            let down = migration
+                .down
+                .as_deref()
+                .ok_or_else(|| MigrateError::Irreversible {
+                    version: *version,
+                    name: name.clone(),
+                })?;
+
+            let mut tx = conn.begin().await?;
+            tx.execute(down).await?;
+            let sql = format!("DELETE FROM _migrations WHERE version = {}", placeholder(1));
+            sqlx::query(&sql).bind(version).execute(&mut *tx).await?;
+            tx.commit().await?;
+
+            tracing::info!(
+                "Reverted migration {}_{}",
+                migration.version,
+                migration.name
+            );
+            done.push(migration);
+        }
+
+        Ok(done)
+    }
+}
+
+/// A connection holding the migration lock. Dropped without
+/// [`Lock::release`], after an error or when the migrating future is
+/// cancelled, the connection is closed instead of going back to the pool
+/// still holding the lock, as ending its session releases the lock.
+struct Lock(Option<PoolConnection<Driver>>);
+
+impl Lock {
+    async fn acquire(db: &DB) -> Result<Self, sqlx::Error> {
+        let mut lock = Lock(Some(db.writer().acquire().await?));
+        let conn = lock.conn();
+        match DRIVER {
+            config::Driver::Postgres => {
+                sqlx::query("SELECT pg_advisory_lock($1)")
+                    .bind(LOCK_KEY)
+                    .execute(&mut **conn)
+                    .await?;
+            }
+            config::Driver::Mysql => {
+                sqlx::query("SELECT GET_LOCK(?, -1)")
+                    .bind(LOCK_NAME)
+                    .execute(&mut **conn)
+                    .await?;
+            }
+            config::Driver::Sqlite => {}
+        }
+        conn.execute(CREATE_TABLE).await?;
+        Ok(lock)
+    }
+
+    fn conn(&mut self) -> &mut PoolConnection<Driver> {
+        self.0
+            .as_mut()
+            .expect("the connection is only taken when released")
+    }
+
+    async fn release(mut self) -> Result<(), sqlx::Error> {
+        let conn = self.conn();
+        match DRIVER {
+            config::Driver::Postgres => {
+                sqlx::query("SELECT pg_advisory_unlock($1)")
+                    .bind(LOCK_KEY)
+                    .execute(&mut **conn)
+                    .await?;
+            }
+            config::Driver::Mysql => {
+                sqlx::query("SELECT RELEASE_LOCK(?)")
+                    .bind(LOCK_NAME)
+                    .execute(&mut **conn)
+                    .await?;
+            }
+            config::Driver::Sqlite => {}
+        }
+        // Unlocked, so the connection can be reused.
+        self.0.take();
+        Ok(())
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        if let Some(conn) = self.0.take() {
+            drop(conn.detach());
+        }
+    }
+}
+
+async fn applied(
+    conn: &mut PoolConnection<Driver>,
+) -> Result<Vec<(i64, String, String, DateTime<Utc>)>, sqlx::Error> {
+    sqlx::query_as("SELECT version, name, checksum, applied_at FROM _migrations ORDER BY version")
+        .fetch_all(&mut **conn)
+        .await
+}
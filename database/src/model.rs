@@ -0,0 +1,478 @@
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use sqlx::{Acquire, Arguments, Encode, Executor, FromRow, Type};
+
+use crate::{
+    audit::{self, Action},
+    driver::{placeholder, RETURNING},
+    page::{self, Page, PageError, PageRequest},
+    Args, Connection, Driver, Row,
+};
+
+/// A struct mapped to the rows of a table, usually derived:
+///
+/// ```ignore
+/// #[derive(FromRow, Model)]
+/// #[model(table = "posts")]
+/// pub struct PostDB {
+///     #[model(primary_key)]
+///     pub id: i64,
+///     pub title: Option<String>,
+///     #[model(default = "current_timestamp")]
+///     pub created_at: Option<DateTime<Utc>>,
+///     #[model(default = "current_timestamp", on_update = "current_timestamp")]
+///     pub updated_at: Option<DateTime<Utc>>,
+/// }
+/// ```
+///
+/// The table defaults to the snake case struct name with an `s` appended.
+/// Struct attributes, besides `table`:
+///
+/// - `timestamps`: sets `created_at` on insert and `updated_at` on insert
+///   and update to the current time, as the attributes above do.
+/// - `soft_delete`: [`Repository::delete`] sets the `deleted_at` column
+///   instead of removing the row, and reads leave out such rows. See
+///   [`Trashed`].
+/// - `audit`: records every write in the [`AUDIT_TABLE`](crate::AUDIT_TABLE)
+///   along with the row before and after it, as JSON. The struct must
+///   implement `Serialize`.
+///
+/// Field attributes:
+///
+/// - `primary_key`: the key of [`Repository::find`] and friends. It is
+///   left to the database on insert unless also marked `insertable`.
+/// - `default = "<sql>"`: written as the SQL expression on insert and left
+///   alone on update, for columns such as `created_at`.
+/// - `on_update = "<sql>"`: written as the SQL expression on update.
+/// - `rename = "<column>"`: the column name, when it differs from the field.
+/// - `sortable`, `filterable`: allows [`Repository::paginate`] to sort or
+///   filter by the column.
+///
+/// Every [`Model`] is a [`Repository`].
+pub trait Model: for<'r> FromRow<'r, Row> + Send + Sync + Unpin + Sized {
+    type Key: for<'q> Encode<'q, Driver> + Type<Driver> + Clone + Send + Sync + 'static;
+
+    const TABLE: &'static str;
+    const PRIMARY_KEY: &'static str;
+    const COLUMNS: &'static [Column];
+    /// The `deleted_at` column, with `soft_delete`.
+    const SOFT_DELETE: Option<&'static str> = None;
+    /// Whether writes are recorded in the audit log, with `audit`.
+    const AUDIT: bool = false;
+
+    fn key(&self) -> Self::Key;
+
+    /// Binds the fields whose column has [`Value::Bind`] on insert, in
+    /// [`Model::COLUMNS`] order.
+    fn bind_insert<'q>(&'q self, args: &mut Args<'q>);
+
+    /// Binds the fields whose column has [`Value::Bind`] on update, in
+    /// [`Model::COLUMNS`] order.
+    fn bind_update<'q>(&'q self, args: &mut Args<'q>);
+
+    /// The row as recorded in the audit log, `None` without `audit`.
+    fn to_audit_json(&self) -> Option<serde_json::Value> {
+        None
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Column {
+    pub name: &'static str,
+    pub primary_key: bool,
+    pub sortable: bool,
+    pub filterable: bool,
+    /// What an insert writes to the column.
+    pub insert: Value,
+    /// What an update writes to the column.
+    pub update: Value,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Value {
+    /// The value of the field, as a bind parameter.
+    Bind,
+    /// An SQL expression such as `current_timestamp`.
+    Sql(&'static str),
+    /// Leaves the column out of the statement.
+    Skip,
+}
+
+/// Which rows of a `soft_delete` model a read returns. Has no effect on
+/// other models.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Trashed {
+    /// Only the rows that are not deleted.
+    #[default]
+    Without,
+    /// Deleted rows too.
+    With,
+    /// Only the deleted rows.
+    Only,
+}
+
+/// CRUD on the table of a [`Model`]. Reads take any executor, writes
+/// anything a connection can be acquired from: both accept a pool, a
+/// connection or a transaction.
+///
+/// Writes to an audited model run in a transaction, or a savepoint of the
+/// current one, along with their audit record.
+///
+/// Errors are plain [`sqlx::Error`], handlers turn them into
+/// `system::Error` with `?`.
+#[async_trait]
+pub trait Repository: Model {
+    async fn all<'e, E>(db: E) -> Result<Vec<Self>, sqlx::Error>
+    where
+        E: Executor<'e, Database = Driver>,
+    {
+        sqlx::query_as(&select::<Self>(Trashed::Without))
+            .fetch_all(db)
+            .await
+    }
+
+    /// Every row, soft deleted ones included.
+    async fn all_with_trashed<'e, E>(db: E) -> Result<Vec<Self>, sqlx::Error>
+    where
+        E: Executor<'e, Database = Driver>,
+    {
+        sqlx::query_as(&select::<Self>(Trashed::With))
+            .fetch_all(db)
+            .await
+    }
+
+    async fn find<'e, E>(db: E, key: Self::Key) -> Result<Self, sqlx::Error>
+    where
+        E: Executor<'e, Database = Driver>,
+    {
+        sqlx::query_as(&select_by_key::<Self>(&placeholder(1), Trashed::Without))
+            .bind(key)
+            .fetch_one(db)
+            .await
+    }
+
+    /// The row with `key`, even when soft deleted.
+    async fn find_with_trashed<'e, E>(db: E, key: Self::Key) -> Result<Self, sqlx::Error>
+    where
+        E: Executor<'e, Database = Driver>,
+    {
+        sqlx::query_as(&select_by_key::<Self>(&placeholder(1), Trashed::With))
+            .bind(key)
+            .fetch_one(db)
+            .await
+    }
+
+    /// Inserts `self` and returns the row as stored, with the generated key
+    /// and defaults filled in.
+    async fn insert<'a, A>(&self, db: A) -> Result<Self, sqlx::Error>
+    where
+        A: Acquire<'a, Database = Driver> + Send,
+    {
+        let mut conn = db.acquire().await?;
+        if !Self::AUDIT {
+            return insert_row(&mut conn, self).await;
+        }
+
+        let mut tx = sqlx::Connection::begin(&mut *conn).await?;
+        let row = insert_row(&mut tx, self).await?;
+        audit::record(&mut tx, Action::Insert, None, Some(&row)).await?;
+        tx.commit().await?;
+        Ok(row)
+    }
+
+    /// Updates the row with the key of `self` and returns it as stored.
+    /// Soft deleted rows are left alone, failing with
+    /// [`sqlx::Error::RowNotFound`] like missing ones, until restored.
+    async fn update<'a, A>(&self, db: A) -> Result<Self, sqlx::Error>
+    where
+        A: Acquire<'a, Database = Driver> + Send,
+    {
+        let mut conn = db.acquire().await?;
+        if !Self::AUDIT {
+            return update_row(&mut conn, self).await;
+        }
+
+        let mut tx = sqlx::Connection::begin(&mut *conn).await?;
+        let old = Self::find_with_trashed(&mut *tx, self.key()).await?;
+        let row = update_row(&mut tx, self).await?;
+        audit::record(&mut tx, Action::Update, Some(&old), Some(&row)).await?;
+        tx.commit().await?;
+        Ok(row)
+    }
+
+    /// The page of rows described by `request`, see [`PageRequest`].
+    async fn paginate<'a, A>(db: A, request: &PageRequest) -> Result<Page<Self>, PageError>
+    where
+        A: Acquire<'a, Database = Driver> + Send,
+        Self::Key: FromStr,
+    {
+        page::paginate(db, request).await
+    }
+
+    /// Deletes the row with `key` and returns it. With `soft_delete` the row
+    /// is only marked deleted, and can be brought back with
+    /// [`Repository::restore`].
+    async fn delete<'a, A>(db: A, key: Self::Key) -> Result<Self, sqlx::Error>
+    where
+        A: Acquire<'a, Database = Driver> + Send,
+    {
+        let Some(column) = Self::SOFT_DELETE else {
+            return Self::force_delete(db, key).await;
+        };
+
+        let mut conn = db.acquire().await?;
+        if !Self::AUDIT {
+            return mark_deleted(&mut conn, column, key, true).await;
+        }
+
+        let mut tx = sqlx::Connection::begin(&mut *conn).await?;
+        let old = Self::find_with_trashed(&mut *tx, key.clone()).await?;
+        let row = mark_deleted(&mut tx, column, key, true).await?;
+        audit::record(&mut tx, Action::Delete, Some(&old), Some(&row)).await?;
+        tx.commit().await?;
+        Ok(row)
+    }
+
+    /// Brings back the soft deleted row with `key` and returns it.
+    async fn restore<'a, A>(db: A, key: Self::Key) -> Result<Self, sqlx::Error>
+    where
+        A: Acquire<'a, Database = Driver> + Send,
+    {
+        let Some(column) = Self::SOFT_DELETE else {
+            return Err(sqlx::Error::ColumnNotFound(format!(
+                "{} has no soft delete column",
+                Self::TABLE
+            )));
+        };
+
+        let mut conn = db.acquire().await?;
+        if !Self::AUDIT {
+            return mark_deleted(&mut conn, column, key, false).await;
+        }
+
+        let mut tx = sqlx::Connection::begin(&mut *conn).await?;
+        let old = Self::find_with_trashed(&mut *tx, key.clone()).await?;
+        let row = mark_deleted(&mut tx, column, key, false).await?;
+        audit::record(&mut tx, Action::Restore, Some(&old), Some(&row)).await?;
+        tx.commit().await?;
+        Ok(row)
+    }
+
+    /// Removes the row with `key` for good, even with `soft_delete`, and
+    /// returns it.
+    async fn force_delete<'a, A>(db: A, key: Self::Key) -> Result<Self, sqlx::Error>
+    where
+        A: Acquire<'a, Database = Driver> + Send,
+    {
+        let mut conn = db.acquire().await?;
+        if !Self::AUDIT {
+            return delete_row(&mut conn, key).await;
+        }
+
+        let mut tx = sqlx::Connection::begin(&mut *conn).await?;
+        let row = delete_row::<Self>(&mut tx, key).await?;
+        audit::record(&mut tx, Action::Delete, Some(&row), None).await?;
+        tx.commit().await?;
+        Ok(row)
+    }
+}
+
+impl<M: Model> Repository for M {}
+
+async fn insert_row<M: Model>(conn: &mut Connection, model: &M) -> Result<M, sqlx::Error> {
+    let (names, values) = assignments(M::COLUMNS.iter().map(|c| (c.name, c.insert)));
+    let sql = if names.is_empty() {
+        match crate::DRIVER {
+            config::Driver::Mysql => format!("INSERT INTO {} () VALUES ()", M::TABLE),
+            _ => format!("INSERT INTO {} DEFAULT VALUES", M::TABLE),
+        }
+    } else {
+        format!(
+            "INSERT INTO {} ({}) VALUES ({})",
+            M::TABLE,
+            names.join(", "),
+            values.join(", ")
+        )
+    };
+
+    let mut args = Args::default();
+    model.bind_insert(&mut args);
+    if RETURNING {
+        let sql = format!("{} RETURNING {}", sql, select_list::<M>());
+        return sqlx::query_as_with(&sql, args).fetch_one(conn).await;
+    }
+
+    sqlx::query_with(&sql, args).execute(&mut *conn).await?;
+    let key_bound = M::COLUMNS
+        .iter()
+        .any(|c| c.primary_key && c.insert == Value::Bind);
+    if key_bound {
+        M::find_with_trashed(conn, model.key()).await
+    } else {
+        sqlx::query_as(&select_by_key::<M>("LAST_INSERT_ID()", Trashed::With))
+            .fetch_one(conn)
+            .await
+    }
+}
+
+async fn update_row<M: Model>(conn: &mut Connection, model: &M) -> Result<M, sqlx::Error> {
+    let (names, values) = assignments(M::COLUMNS.iter().map(|c| (c.name, c.update)));
+    let binds = M::COLUMNS
+        .iter()
+        .filter(|c| c.update == Value::Bind)
+        .count();
+    let set: Vec<String> = names
+        .iter()
+        .zip(&values)
+        .map(|(name, value)| format!("{} = {}", name, value))
+        .collect();
+    let mut sql = format!(
+        "UPDATE {} SET {} WHERE {} = {}",
+        M::TABLE,
+        set.join(", "),
+        M::PRIMARY_KEY,
+        placeholder(binds + 1)
+    );
+    if let Some(condition) = trashed_condition::<M>(Trashed::Without) {
+        sql = format!("{} AND {}", sql, condition);
+    }
+
+    let mut args = Args::default();
+    model.bind_update(&mut args);
+    args.add(model.key());
+    if RETURNING {
+        let sql = format!("{} RETURNING {}", sql, select_list::<M>());
+        return sqlx::query_as_with(&sql, args).fetch_one(conn).await;
+    }
+
+    let result = sqlx::query_with(&sql, args).execute(&mut *conn).await?;
+    if result.rows_affected() == 0 {
+        return Err(sqlx::Error::RowNotFound);
+    }
+    M::find_with_trashed(conn, model.key()).await
+}
+
+/// Sets or clears the soft delete `column` of a row that is not already in
+/// that state, failing with [`sqlx::Error::RowNotFound`] otherwise.
+async fn mark_deleted<M: Model>(
+    conn: &mut Connection,
+    column: &str,
+    key: M::Key,
+    deleted: bool,
+) -> Result<M, sqlx::Error> {
+    let (value, state) = if deleted {
+        ("current_timestamp", "IS NULL")
+    } else {
+        ("NULL", "IS NOT NULL")
+    };
+    let sql = format!(
+        "UPDATE {} SET {column} = {} WHERE {} = {} AND {column} {}",
+        M::TABLE,
+        value,
+        M::PRIMARY_KEY,
+        placeholder(1),
+        state,
+        column = column
+    );
+
+    if RETURNING {
+        let sql = format!("{} RETURNING {}", sql, select_list::<M>());
+        return sqlx::query_as(&sql).bind(key).fetch_one(conn).await;
+    }
+
+    let result = sqlx::query(&sql)
+        .bind(key.clone())
+        .execute(&mut *conn)
+        .await?;
+    if result.rows_affected() == 0 {
+        return Err(sqlx::Error::RowNotFound);
+    }
+    M::find_with_trashed(conn, key).await
+}
+
+async fn delete_row<M: Model>(conn: &mut Connection, key: M::Key) -> Result<M, sqlx::Error> {
+    let sql = format!(
+        "DELETE FROM {} WHERE {} = {}",
+        M::TABLE,
+        M::PRIMARY_KEY,
+        placeholder(1)
+    );
+
+    if RETURNING {
+        let sql = format!("{} RETURNING {}", sql, select_list::<M>());
+        return sqlx::query_as(&sql).bind(key).fetch_one(conn).await;
+    }
+
+    let row = M::find_with_trashed(&mut *conn, key.clone()).await?;
+    sqlx::query(&sql).bind(key).execute(conn).await?;
+    Ok(row)
+}
+
+pub(crate) fn select_list<M: Model>() -> String {
+    M::COLUMNS
+        .iter()
+        .map(|column| column.name)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// The condition leaving out the rows of `M` that `trashed` excludes, if
+/// any.
+pub(crate) fn trashed_condition<M: Model>(trashed: Trashed) -> Option<String> {
+    let column = M::SOFT_DELETE?;
+    match trashed {
+        Trashed::Without => Some(format!("{} IS NULL", column)),
+        Trashed::With => None,
+        Trashed::Only => Some(format!("{} IS NOT NULL", column)),
+    }
+}
+
+fn select<M: Model>(trashed: Trashed) -> String {
+    let sql = format!("SELECT {} FROM {}", select_list::<M>(), M::TABLE);
+    match trashed_condition::<M>(trashed) {
+        Some(condition) => format!("{} WHERE {}", sql, condition),
+        None => sql,
+    }
+}
+
+fn select_by_key<M: Model>(key: &str, trashed: Trashed) -> String {
+    let sql = format!(
+        "SELECT {} FROM {} WHERE {} = {}",
+        select_list::<M>(),
+        M::TABLE,
+        M::PRIMARY_KEY,
+        key
+    );
+    match trashed_condition::<M>(trashed) {
+        Some(condition) => format!("{} AND {}", sql, condition),
+        None => sql,
+    }
+}
+
+/// The column names and values of a statement, numbering bind parameters
+/// from 1.
+fn assignments(
+    columns: impl Iterator<Item = (&'static str, Value)>,
+) -> (Vec<&'static str>, Vec<String>) {
+    let mut names = Vec::new();
+    let mut values = Vec::new();
+    let mut n = 0;
+
+    for (name, value) in columns {
+        match value {
+            Value::Bind => {
+                n += 1;
+                names.push(name);
+                values.push(placeholder(n));
+            }
+            Value::Sql(sql) => {
+                names.push(name);
+                values.push(sql.to_string());
+            }
+            Value::Skip => {}
+        }
+    }
+
+    (names, values)
+}
@@ -0,0 +1,179 @@
+use std::{
+    collections::HashMap,
+    marker::PhantomData,
+    sync::{Arc, Mutex},
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+use sqlx::Executor;
+use tokio::sync::broadcast;
+
+use crate::{Driver, DB};
+
+/// Notifications a slow subscriber may fall behind by before it misses some.
+#[cfg(not(any(feature = "mysql", feature = "sqlite")))]
+const CAPACITY: usize = 256;
+
+/// The channels listened to by a [`DB`], each fanned out to its subscribers.
+#[derive(Default)]
+pub(crate) struct Channels {
+    senders: Mutex<HashMap<String, broadcast::Sender<Arc<str>>>>,
+}
+
+/// Sends `event` as JSON to the subscribers of `channel`, in this process
+/// and every other one connected to the database. Inside a transaction it
+/// is only delivered on commit. Postgres limits the JSON to 8000 bytes.
+pub async fn notify<'e, E, T>(db: E, channel: &str, event: &T) -> Result<(), sqlx::Error>
+where
+    E: Executor<'e, Database = Driver>,
+    T: Serialize + ?Sized,
+{
+    if crate::DRIVER != config::Driver::Postgres {
+        return Err(unsupported());
+    }
+
+    let payload = serde_json::to_string(event).map_err(|e| sqlx::Error::Io(e.into()))?;
+    sqlx::query("SELECT pg_notify($1, $2)")
+        .bind(channel)
+        .bind(payload)
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+/// The events of a channel, see [`DB::subscribe`].
+pub struct Subscription<T> {
+    channel: String,
+    receiver: broadcast::Receiver<Arc<str>>,
+    event: PhantomData<fn() -> T>,
+}
+
+impl<T: DeserializeOwned> Subscription<T> {
+    pub fn channel(&self) -> &str {
+        &self.channel
+    }
+
+    /// The next event, skipping those that do not deserialize into `T`.
+    /// Returns `None` once the database is closed.
+    pub async fn recv(&mut self) -> Option<T> {
+        loop {
+            match self.receiver.recv().await {
+                Ok(payload) => match serde_json::from_str(&payload) {
+                    Ok(event) => return Some(event),
+                    Err(e) => tracing::warn!(
+                        "Ignoring a notification on {} that does not match its type: {}",
+                        self.channel,
+                        e
+                    ),
+                },
+                Err(broadcast::error::RecvError::Lagged(missed)) => tracing::warn!(
+                    "Subscriber of {} fell behind and missed {} notifications",
+                    self.channel,
+                    missed
+                ),
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+impl DB {
+    /// Subscribes to the events sent with [`notify`] on `channel`.
+    ///
+    /// The first subscription to a channel starts listening to it, which
+    /// holds one connection of the pool for as long as the process runs.
+    /// Later ones share it. Notifications sent while the connection is
+    /// being reestablished are lost.
+    ///
+    /// ```ignore
+    /// let mut posts = db.subscribe::<PostEvent>("posts").await?;
+    /// while let Some(event) = posts.recv().await {
+    ///     // ...
+    /// }
+    /// ```
+    pub async fn subscribe<T: DeserializeOwned>(
+        &self,
+        channel: &str,
+    ) -> Result<Subscription<T>, sqlx::Error> {
+        let existing = self
+            .channels
+            .senders
+            .lock()
+            .unwrap()
+            .get(channel)
+            .map(|sender| sender.subscribe());
+        let receiver = match existing {
+            Some(receiver) => receiver,
+            None => self.listen(channel).await?,
+        };
+
+        Ok(Subscription {
+            channel: channel.to_string(),
+            receiver,
+            event: PhantomData,
+        })
+    }
+
+    /// Sends `event` to the subscribers of `channel` through the primary,
+    /// see [`notify`].
+    pub async fn notify<T: Serialize + ?Sized>(
+        &self,
+        channel: &str,
+        event: &T,
+    ) -> Result<(), sqlx::Error> {
+        notify(self.writer(), channel, event).await
+    }
+
+    #[cfg(not(any(feature = "mysql", feature = "sqlite")))]
+    async fn listen(&self, channel: &str) -> Result<broadcast::Receiver<Arc<str>>, sqlx::Error> {
+        let mut listener = sqlx::postgres::PgListener::connect_with(self.writer()).await?;
+        listener.listen(channel).await?;
+
+        // Another subscriber may have started listening meanwhile.
+        let mut senders = self.channels.senders.lock().unwrap();
+        if let Some(sender) = senders.get(channel) {
+            return Ok(sender.subscribe());
+        }
+        let (sender, receiver) = broadcast::channel(CAPACITY);
+        senders.insert(channel.to_string(), sender.clone());
+        drop(senders);
+
+        let channels = Arc::downgrade(&self.channels);
+        let channel = channel.to_string();
+        tokio::spawn(async move {
+            loop {
+                match listener.recv().await {
+                    Ok(notification) => {
+                        // Nobody may be subscribed right now, which is fine.
+                        let _ = sender.send(notification.payload().into());
+                    }
+                    Err(sqlx::Error::PoolClosed) => break,
+                    Err(e) => {
+                        tracing::warn!("Failed to receive notifications on {}: {}", channel, e);
+                        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                    }
+                }
+            }
+            if let Some(channels) = channels.upgrade() {
+                channels.senders.lock().unwrap().remove(&channel);
+            }
+        });
+
+        Ok(receiver)
+    }
+
+    #[cfg(any(feature = "mysql", feature = "sqlite"))]
+    async fn listen(&self, _: &str) -> Result<broadcast::Receiver<Arc<str>>, sqlx::Error> {
+        Err(unsupported())
+    }
+}
+
+fn unsupported() -> sqlx::Error {
+    sqlx::Error::Configuration(
+        format!(
+            "notifications need the postgres driver, not {}",
+            crate::DRIVER
+        )
+        .into(),
+    )
+}
@@ -0,0 +1,670 @@
+use std::str::FromStr;
+
+use serde::Serialize;
+use sqlx::{Acquire, Arguments, Row};
+
+use crate::{
+    driver::{placeholder, TEXT},
+    model::{select_list, trashed_condition},
+    Args, Column, Driver, Model, Trashed,
+};
+
+pub const DEFAULT_PER_PAGE: u64 = 20;
+pub const MAX_PER_PAGE: u64 = 100;
+
+#[derive(Debug)]
+pub enum PageError {
+    InvalidQuery(String),
+    InvalidSort(String),
+    InvalidFilter(String),
+    InvalidCursor(String),
+    Database(sqlx::Error),
+}
+
+impl From<sqlx::Error> for PageError {
+    fn from(e: sqlx::Error) -> Self {
+        PageError::Database(e)
+    }
+}
+
+impl std::error::Error for PageError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PageError::Database(source) => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for PageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PageError::InvalidQuery(message) => write!(f, "Invalid pagination: {}", message),
+            PageError::InvalidSort(column) => write!(f, "Cannot sort by `{}`", column),
+            PageError::InvalidFilter(column) => write!(f, "Cannot filter by `{}`", column),
+            PageError::InvalidCursor(cursor) => write!(f, "Invalid cursor `{}`", cursor),
+            PageError::Database(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Mode {
+    /// The 1-based page number, counted with `OFFSET`.
+    Offset(u64),
+    /// The cursor of the last row seen, `None` for the first page. Rows are
+    /// looked up by comparing the sort column, which stays fast and stable
+    /// on large tables that change while being paged through. When sorting
+    /// by a column, the cursor row must still exist, since its value is the
+    /// one compared to.
+    Keyset(Option<String>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Order {
+    #[default]
+    Asc,
+    Desc,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sort {
+    pub column: String,
+    pub order: Order,
+}
+
+/// Which page of a list to fetch, usually parsed from the query string:
+///
+/// - `page=3` for offset pagination, or `after=<cursor>` for keyset
+///   pagination, where an empty `after=` asks for the first page,
+/// - `per_page=50`, capped at [`MAX_PER_PAGE`],
+/// - `sort=title`, or `sort=-title` for descending order,
+/// - `filter[title]=Hello` to only list rows whose column equals the value.
+///
+/// Sorting and filtering are limited to the columns of the model marked
+/// `sortable` and `filterable`. NULLs sort after every value, or before
+/// them in descending order, whatever the database. Soft deleted rows are
+/// left out unless `trashed` is set, which the query string cannot do.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PageRequest {
+    pub mode: Mode,
+    pub per_page: u64,
+    pub sort: Option<Sort>,
+    pub filters: Vec<(String, String)>,
+    pub trashed: Trashed,
+    /// The path the links of the [`Page`] point to.
+    pub path: String,
+}
+
+impl Default for PageRequest {
+    fn default() -> Self {
+        Self {
+            mode: Mode::Offset(1),
+            per_page: DEFAULT_PER_PAGE,
+            sort: None,
+            filters: Vec::new(),
+            trashed: Trashed::default(),
+            path: String::new(),
+        }
+    }
+}
+
+impl PageRequest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_query(path: &str, query: &str) -> Result<Self, PageError> {
+        let pairs: Vec<(String, String)> = serde_urlencoded::from_str(query)
+            .map_err(|e| PageError::InvalidQuery(e.to_string()))?;
+        let number = |key: &str, value: &str| match value.parse::<u64>() {
+            Ok(n) if n > 0 => Ok(n),
+            _ => Err(PageError::InvalidQuery(format!(
+                "`{}` must be a positive number",
+                key
+            ))),
+        };
+
+        let mut request = PageRequest {
+            path: path.to_string(),
+            ..Default::default()
+        };
+        for (key, value) in pairs {
+            match key.as_str() {
+                "page" => request.mode = Mode::Offset(number(&key, &value)?),
+                "after" => request.mode = Mode::Keyset(Some(value).filter(|v| !v.is_empty())),
+                "per_page" => request.per_page = number(&key, &value)?.min(MAX_PER_PAGE),
+                "sort" => {
+                    request.sort = Some(match value.strip_prefix('-') {
+                        Some(column) => Sort {
+                            column: column.to_string(),
+                            order: Order::Desc,
+                        },
+                        None => Sort {
+                            column: value,
+                            order: Order::Asc,
+                        },
+                    })
+                }
+                _ => {
+                    if let Some(column) = key
+                        .strip_prefix("filter[")
+                        .and_then(|key| key.strip_suffix(']'))
+                    {
+                        request.filters.push((column.to_string(), value));
+                    }
+                }
+            }
+        }
+
+        Ok(request)
+    }
+
+    /// The link to another page of the same list, keeping the page size,
+    /// sort and filters.
+    pub fn link(&self, mode: &Mode) -> String {
+        let mut pairs = Vec::new();
+        if self.per_page != DEFAULT_PER_PAGE {
+            pairs.push(("per_page".to_string(), self.per_page.to_string()));
+        }
+        if let Some(sort) = &self.sort {
+            let prefix = if sort.order == Order::Desc { "-" } else { "" };
+            pairs.push(("sort".to_string(), format!("{}{}", prefix, sort.column)));
+        }
+        for (column, value) in &self.filters {
+            pairs.push((format!("filter[{}]", column), value.clone()));
+        }
+        match mode {
+            Mode::Offset(page) => pairs.push(("page".to_string(), page.to_string())),
+            Mode::Keyset(after) => {
+                pairs.push(("after".to_string(), after.clone().unwrap_or_default()))
+            }
+        }
+
+        format!(
+            "{}?{}",
+            self.path,
+            serde_urlencoded::to_string(pairs).unwrap_or_default()
+        )
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Number of rows matching the filters, across all pages.
+    pub total: i64,
+    pub per_page: u64,
+    /// The current page and the number of pages, in offset mode only.
+    pub page: Option<u64>,
+    pub pages: Option<u64>,
+    /// The cursor of the next page, in keyset mode only.
+    pub next_cursor: Option<String>,
+    pub links: Links,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Links {
+    pub first: String,
+    pub prev: Option<String>,
+    pub next: Option<String>,
+    /// Only known in offset mode.
+    pub last: Option<String>,
+}
+
+impl Links {
+    fn offset(request: &PageRequest, page: u64, pages: u64) -> Self {
+        Links {
+            first: request.link(&Mode::Offset(1)),
+            prev: (page > 1).then(|| request.link(&Mode::Offset((page - 1).min(pages)))),
+            next: (page < pages).then(|| request.link(&Mode::Offset(page + 1))),
+            last: Some(request.link(&Mode::Offset(pages))),
+        }
+    }
+
+    fn keyset(request: &PageRequest, next_cursor: Option<&str>) -> Self {
+        Links {
+            first: request.link(&Mode::Keyset(None)),
+            prev: None,
+            next: next_cursor.map(|cursor| request.link(&Mode::Keyset(Some(cursor.to_string())))),
+            last: None,
+        }
+    }
+}
+
+impl<T> Page<T> {
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Page<U> {
+        Page {
+            items: self.items.into_iter().map(f).collect(),
+            total: self.total,
+            per_page: self.per_page,
+            page: self.page,
+            pages: self.pages,
+            next_cursor: self.next_cursor,
+            links: self.links,
+        }
+    }
+}
+
+pub(crate) async fn paginate<'a, M, A>(db: A, request: &PageRequest) -> Result<Page<M>, PageError>
+where
+    M: Model,
+    M::Key: FromStr,
+    A: Acquire<'a, Database = Driver>,
+{
+    let sort = allowed_sort(M::COLUMNS, request)?;
+    let order = request
+        .sort
+        .as_ref()
+        .map(|sort| sort.order)
+        .unwrap_or_default();
+
+    // Filters compare as text, since the value comes from a query string
+    // and would not match the type of a non-text column otherwise.
+    let mut conditions: Vec<String> = request
+        .filters
+        .iter()
+        .enumerate()
+        .map(|(i, (name, _))| format!("CAST({} AS {}) = {}", name, TEXT, placeholder(i + 1)))
+        .chain(trashed_condition::<M>(request.trashed))
+        .collect();
+    let filter_args = || {
+        let mut args = Args::default();
+        for (_, value) in &request.filters {
+            args.add(value.clone());
+        }
+        args
+    };
+    let where_clause = |conditions: &[String]| {
+        if conditions.is_empty() {
+            String::new()
+        } else {
+            format!(" WHERE {}", conditions.join(" AND "))
+        }
+    };
+
+    let mut conn = db.acquire().await?;
+    let (total,): (i64,) = sqlx::query_as_with(
+        &format!(
+            "SELECT COUNT(*) FROM {}{}",
+            M::TABLE,
+            where_clause(&conditions)
+        ),
+        filter_args(),
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    let direction = match order {
+        Order::Asc => "ASC",
+        Order::Desc => "DESC",
+    };
+    // The primary key breaks ties, so rows never move between pages. NULLs
+    // are ordered explicitly, as each database puts them somewhere else.
+    let order_by = match sort {
+        Some(column) => format!(
+            "{column} IS NULL {direction}, {column} {direction}, {key} {direction}",
+            column = column,
+            direction = direction,
+            key = M::PRIMARY_KEY
+        ),
+        None => format!("{} {}", M::PRIMARY_KEY, direction),
+    };
+    let per_page = request.per_page.clamp(1, MAX_PER_PAGE);
+
+    match &request.mode {
+        Mode::Offset(page) => {
+            let page = (*page).max(1);
+            let pages = (total.max(0) as u64).div_ceil(per_page).max(1);
+            let sql = format!(
+                "SELECT {} FROM {}{} ORDER BY {} LIMIT {} OFFSET {}",
+                select_list::<M>(),
+                M::TABLE,
+                where_clause(&conditions),
+                order_by,
+                per_page,
+                (page - 1).saturating_mul(per_page)
+            );
+            let items = sqlx::query_as_with(&sql, filter_args())
+                .fetch_all(&mut *conn)
+                .await?;
+
+            Ok(Page {
+                items,
+                total,
+                per_page,
+                page: Some(page),
+                pages: Some(pages),
+                next_cursor: None,
+                links: Links::offset(request, page, pages),
+            })
+        }
+        Mode::Keyset(after) => {
+            let mut args = filter_args();
+            if let Some(after) = after {
+                let key: M::Key = after
+                    .parse()
+                    .map_err(|_| PageError::InvalidCursor(after.clone()))?;
+
+                let n = request.filters.len();
+                conditions.push(match sort {
+                    Some(column) => {
+                        // Whether the cursor row exists, and whether its
+                        // value is NULL, which no comparison matches.
+                        let (found, values): (i64, i64) = sqlx::query_as(&format!(
+                            "SELECT COUNT(*), COUNT({}) FROM {} WHERE {} = {}",
+                            column,
+                            M::TABLE,
+                            M::PRIMARY_KEY,
+                            placeholder(1)
+                        ))
+                        .bind(key.clone())
+                        .fetch_one(&mut *conn)
+                        .await?;
+                        if found == 0 {
+                            return Err(PageError::InvalidCursor(after.clone()));
+                        }
+                        after_condition::<M>(column, order, values == 0, key, n, &mut args)
+                    }
+                    None => {
+                        args.add(key);
+                        let operator = match order {
+                            Order::Asc => ">",
+                            Order::Desc => "<",
+                        };
+                        format!("{} {} {}", M::PRIMARY_KEY, operator, placeholder(n + 1))
+                    }
+                });
+            }
+
+            // One row more than asked tells whether there is a next page.
+            let sql = format!(
+                "SELECT {}, CAST({} AS {}) AS _cursor FROM {}{} ORDER BY {} LIMIT {}",
+                select_list::<M>(),
+                M::PRIMARY_KEY,
+                TEXT,
+                M::TABLE,
+                where_clause(&conditions),
+                order_by,
+                per_page + 1
+            );
+            let mut rows = sqlx::query_with(&sql, args).fetch_all(&mut *conn).await?;
+            let more = rows.len() as u64 > per_page;
+            rows.truncate(per_page as usize);
+
+            let next_cursor = match rows.last() {
+                Some(row) if more => Some(row.try_get::<String, _>("_cursor")?),
+                _ => None,
+            };
+            let items = rows
+                .iter()
+                .map(M::from_row)
+                .collect::<Result<Vec<_>, _>>()?;
+
+            Ok(Page {
+                items,
+                total,
+                per_page,
+                page: None,
+                pages: None,
+                links: Links::keyset(request, next_cursor.as_deref()),
+                next_cursor,
+            })
+        }
+    }
+}
+
+/// The column to sort by, after checking the sort and filters of `request`
+/// against the `sortable` and `filterable` columns.
+fn allowed_sort(
+    columns: &'static [Column],
+    request: &PageRequest,
+) -> Result<Option<&'static str>, PageError> {
+    let sort = match &request.sort {
+        Some(sort) => Some(
+            columns
+                .iter()
+                .find(|column| column.name == sort.column && column.sortable)
+                .map(|column| column.name)
+                .ok_or_else(|| PageError::InvalidSort(sort.column.clone()))?,
+        ),
+        None => None,
+    };
+    for (name, _) in &request.filters {
+        if !columns
+            .iter()
+            .any(|column| column.name == name && column.filterable)
+        {
+            return Err(PageError::InvalidFilter(name.clone()));
+        }
+    }
+    Ok(sort)
+}
+
+/// The condition matching the rows that come after the row with `key` when
+/// sorting by `column`, which is `null` in that row. Parameters are bound
+/// to `args` from the one after the `bound`th.
+fn after_condition<M: Model>(
+    column: &str,
+    order: Order,
+    null: bool,
+    key: M::Key,
+    bound: usize,
+    args: &mut Args,
+) -> String {
+    let mut n = bound;
+    // MySQL numbers nothing, so the key is bound anew wherever it is used.
+    let mut param = || {
+        n += 1;
+        args.add(key.clone());
+        placeholder(n)
+    };
+
+    if null {
+        return match order {
+            // NULLs come last in ascending order.
+            Order::Asc => format!("({} IS NULL AND {} > {})", column, M::PRIMARY_KEY, param()),
+            // And first in descending order, followed by every value.
+            Order::Desc => format!(
+                "({} IS NOT NULL OR {} < {})",
+                column,
+                M::PRIMARY_KEY,
+                param()
+            ),
+        };
+    }
+
+    let (operator, nulls) = match order {
+        Order::Asc => (">", format!(" OR {} IS NULL", column)),
+        Order::Desc => ("<", String::new()),
+    };
+    let mut value = || {
+        format!(
+            "(SELECT {} FROM {} WHERE {} = {})",
+            column,
+            M::TABLE,
+            M::PRIMARY_KEY,
+            param()
+        )
+    };
+    let after = value();
+    let equal = value();
+    format!(
+        "({column} {operator} {after} OR ({column} = {equal} AND {key} {operator} {param}){nulls})",
+        column = column,
+        operator = operator,
+        after = after,
+        equal = equal,
+        key = M::PRIMARY_KEY,
+        param = param(),
+        nulls = nulls,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Value;
+
+    const COLUMNS: &[Column] = &[
+        Column {
+            name: "id",
+            primary_key: true,
+            sortable: false,
+            filterable: false,
+            insert: Value::Skip,
+            update: Value::Skip,
+        },
+        Column {
+            name: "title",
+            primary_key: false,
+            sortable: true,
+            filterable: true,
+            insert: Value::Bind,
+            update: Value::Bind,
+        },
+    ];
+
+    fn parse(query: &str) -> Result<PageRequest, PageError> {
+        PageRequest::from_query("/post", query)
+    }
+
+    #[test]
+    fn parses_the_query_string() {
+        let request = parse("page=3&per_page=50&sort=-title&filter[title]=Hello").unwrap();
+        assert_eq!(
+            request,
+            PageRequest {
+                mode: Mode::Offset(3),
+                per_page: 50,
+                sort: Some(Sort {
+                    column: "title".to_string(),
+                    order: Order::Desc,
+                }),
+                filters: vec![("title".to_string(), "Hello".to_string())],
+                trashed: Trashed::Without,
+                path: "/post".to_string(),
+            }
+        );
+
+        assert_eq!(parse("").unwrap().mode, Mode::Offset(1));
+        assert_eq!(parse("after=").unwrap().mode, Mode::Keyset(None));
+        assert_eq!(
+            parse("after=42").unwrap().mode,
+            Mode::Keyset(Some("42".to_string()))
+        );
+    }
+
+    #[test]
+    fn caps_per_page() {
+        assert_eq!(parse("per_page=500").unwrap().per_page, MAX_PER_PAGE);
+        assert_eq!(parse("per_page=100").unwrap().per_page, 100);
+    }
+
+    #[test]
+    fn rejects_pages_that_are_not_positive_numbers() {
+        for query in ["page=0", "page=-1", "page=two", "per_page=0"] {
+            assert!(
+                matches!(parse(query), Err(PageError::InvalidQuery(_))),
+                "{}",
+                query
+            );
+        }
+    }
+
+    #[test]
+    fn allows_only_sortable_and_filterable_columns() {
+        let request = parse("sort=title&filter[title]=Hello").unwrap();
+        assert_eq!(allowed_sort(COLUMNS, &request).unwrap(), Some("title"));
+        assert_eq!(allowed_sort(COLUMNS, &parse("").unwrap()).unwrap(), None);
+
+        let request = parse("sort=id").unwrap();
+        assert!(matches!(
+            allowed_sort(COLUMNS, &request),
+            Err(PageError::InvalidSort(column)) if column == "id"
+        ));
+        for query in ["filter[id]=1", "filter[password]=secret"] {
+            assert!(
+                matches!(
+                    allowed_sort(COLUMNS, &parse(query).unwrap()),
+                    Err(PageError::InvalidFilter(_))
+                ),
+                "{}",
+                query
+            );
+        }
+    }
+
+    #[test]
+    fn links_parse_back_to_the_same_request() {
+        let request = PageRequest {
+            per_page: 50,
+            sort: Some(Sort {
+                column: "title".to_string(),
+                order: Order::Desc,
+            }),
+            filters: vec![("title".to_string(), "a&b =c[]".to_string())],
+            path: "/post".to_string(),
+            ..PageRequest::default()
+        };
+
+        for mode in [
+            Mode::Offset(2),
+            Mode::Keyset(None),
+            Mode::Keyset(Some("42".to_string())),
+        ] {
+            let link = request.link(&mode);
+            let (path, query) = link.split_once('?').unwrap();
+            assert_eq!(
+                PageRequest::from_query(path, query).unwrap(),
+                PageRequest {
+                    mode,
+                    ..request.clone()
+                }
+            );
+        }
+        assert_eq!(
+            request.link(&Mode::Offset(2)),
+            "/post?per_page=50&sort=-title&filter%5Btitle%5D=a%26b+%3Dc%5B%5D&page=2"
+        );
+    }
+
+    #[test]
+    fn links_between_offset_pages() {
+        let request = PageRequest {
+            path: "/post".to_string(),
+            ..PageRequest::default()
+        };
+
+        let links = Links::offset(&request, 1, 3);
+        assert_eq!(links.first, "/post?page=1");
+        assert_eq!(links.prev, None);
+        assert_eq!(links.next.as_deref(), Some("/post?page=2"));
+        assert_eq!(links.last.as_deref(), Some("/post?page=3"));
+
+        let links = Links::offset(&request, 3, 3);
+        assert_eq!(links.prev.as_deref(), Some("/post?page=2"));
+        assert_eq!(links.next, None);
+
+        // Past the end, prev leads back to the last page.
+        let links = Links::offset(&request, 7, 3);
+        assert_eq!(links.prev.as_deref(), Some("/post?page=3"));
+        assert_eq!(links.next, None);
+    }
+
+    #[test]
+    fn links_between_keyset_pages() {
+        let request = PageRequest {
+            mode: Mode::Keyset(Some("20".to_string())),
+            path: "/post".to_string(),
+            ..PageRequest::default()
+        };
+
+        let links = Links::keyset(&request, Some("40"));
+        assert_eq!(links.first, "/post?after=");
+        assert_eq!(links.next.as_deref(), Some("/post?after=40"));
+        assert_eq!((links.prev, links.last), (None, None));
+
+        assert_eq!(Links::keyset(&request, None).next, None);
+    }
+}
@@ -0,0 +1,621 @@
+use sqlx::{Arguments, Encode, Executor, FromRow, Type};
+
+use crate::{driver::placeholder, Args, Driver, Order, QueryResult, Row, DRIVER};
+
+/// Stands for a bind parameter in the fragments, until [`Query::to_sql`]
+/// numbers them in the order they appear in the statement.
+const SLOT: char = '\u{1}';
+
+type Bind<'q> = Box<dyn FnOnce(&mut Args<'q>) + Send + 'q>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    Select,
+    Insert,
+    Update,
+    Delete,
+}
+
+/// The clauses holding bind parameters, in statement order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Clause {
+    Values,
+    Joins,
+    Conditions,
+}
+
+struct Conflict {
+    columns: Vec<String>,
+    update: Vec<String>,
+}
+
+/// Builds a statement whose values are always bound as parameters, numbered
+/// in the order they appear in the statement whatever order they are added
+/// in.
+///
+/// ```ignore
+/// let posts: Vec<PostDB> = Query::select("posts")
+///     .where_eq("title", title)
+///     .where_raw("created_at > ?")
+///     .bind(since)
+///     .order_by("created_at", Order::Desc)
+///     .limit(10)
+///     .fetch_all(db.get_pool())
+///     .await?;
+///
+/// let post: PostDB = Query::insert("posts")
+///     .value("title", title)
+///     .value_sql("created_at", "current_timestamp")
+///     .on_conflict(&["slug"])
+///     .do_update(&["title"])
+///     .returning(&["*"])
+///     .fetch_one(&mut *tx)
+///     .await?;
+/// ```
+///
+/// Table names, column names and `*_raw`/`*_sql` fragments are written
+/// into the statement as given and must never come from user input. In raw
+/// fragments every `?` stands for a parameter, bound with [`Query::bind`]
+/// right after the fragment, and `??` for a literal `?`.
+///
+/// MySQL has no `RETURNING`, and ignores the columns of
+/// [`Query::on_conflict`] in favor of any unique key.
+pub struct Query<'q> {
+    kind: Kind,
+    table: String,
+    columns: Vec<String>,
+    values: Vec<String>,
+    joins: Vec<String>,
+    conditions: Vec<String>,
+    order: Vec<String>,
+    limit: Option<u64>,
+    offset: Option<u64>,
+    conflict: Option<Conflict>,
+    returning: Vec<String>,
+    binds: Vec<(Clause, Bind<'q>)>,
+    last: Clause,
+}
+
+impl<'q> Query<'q> {
+    fn new(kind: Kind, table: &str) -> Self {
+        Query {
+            kind,
+            table: table.to_string(),
+            columns: Vec::new(),
+            values: Vec::new(),
+            joins: Vec::new(),
+            conditions: Vec::new(),
+            order: Vec::new(),
+            limit: None,
+            offset: None,
+            conflict: None,
+            returning: Vec::new(),
+            binds: Vec::new(),
+            last: Clause::Conditions,
+        }
+    }
+
+    pub fn select(table: &str) -> Self {
+        Self::new(Kind::Select, table)
+    }
+
+    pub fn insert(table: &str) -> Self {
+        Self::new(Kind::Insert, table)
+    }
+
+    pub fn update(table: &str) -> Self {
+        Self::new(Kind::Update, table)
+    }
+
+    pub fn delete(table: &str) -> Self {
+        Self::new(Kind::Delete, table)
+    }
+
+    /// The selected columns, `*` when none are given.
+    pub fn columns(mut self, columns: &[&str]) -> Self {
+        self.columns
+            .extend(columns.iter().map(|column| column.to_string()));
+        self
+    }
+
+    /// Adds a `JOIN` clause, e.g. `LEFT JOIN users ON users.id = posts.user_id`.
+    pub fn join(mut self, join: &str) -> Self {
+        self.joins.push(slots(join));
+        self.last = Clause::Joins;
+        self
+    }
+
+    pub fn where_eq<T>(self, column: &str, value: T) -> Self
+    where
+        T: Encode<'q, Driver> + Type<Driver> + Send + 'q,
+    {
+        self.where_op(column, "=", value)
+    }
+
+    /// Adds `column <operator> value`, e.g. `where_op("views", ">=", 100)`.
+    pub fn where_op<T>(mut self, column: &str, operator: &str, value: T) -> Self
+    where
+        T: Encode<'q, Driver> + Type<Driver> + Send + 'q,
+    {
+        self.conditions
+            .push(format!("{} {} {}", column, operator, SLOT));
+        self.push(Clause::Conditions, value)
+    }
+
+    /// Adds `column IN (values)`, one parameter per value.
+    pub fn where_in<T>(mut self, column: &str, values: impl IntoIterator<Item = T>) -> Self
+    where
+        T: Encode<'q, Driver> + Type<Driver> + Send + 'q,
+    {
+        let mut slots = Vec::new();
+        for value in values {
+            slots.push(SLOT.to_string());
+            self = self.push(Clause::Conditions, value);
+        }
+        self.conditions.push(if slots.is_empty() {
+            "1 = 0".to_string()
+        } else {
+            format!("{} IN ({})", column, slots.join(", "))
+        });
+        self
+    }
+
+    pub fn where_null(mut self, column: &str) -> Self {
+        self.conditions.push(format!("{} IS NULL", column));
+        self
+    }
+
+    pub fn where_not_null(mut self, column: &str) -> Self {
+        self.conditions.push(format!("{} IS NOT NULL", column));
+        self
+    }
+
+    /// Adds a condition written in SQL, its `?` bound with [`Query::bind`].
+    /// It is parenthesized, so `OR` inside it stays grouped.
+    pub fn where_raw(mut self, condition: &str) -> Self {
+        self.conditions.push(format!("({})", slots(condition)));
+        self.last = Clause::Conditions;
+        self
+    }
+
+    /// Binds the next `?` of the last raw fragment.
+    pub fn bind<T>(self, value: T) -> Self
+    where
+        T: Encode<'q, Driver> + Type<Driver> + Send + 'q,
+    {
+        let clause = self.last;
+        self.push(clause, value)
+    }
+
+    pub fn order_by(mut self, column: &str, order: Order) -> Self {
+        let direction = match order {
+            Order::Asc => "ASC",
+            Order::Desc => "DESC",
+        };
+        self.order.push(format!("{} {}", column, direction));
+        self
+    }
+
+    pub fn limit(mut self, limit: u64) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn offset(mut self, offset: u64) -> Self {
+        self.offset = Some(offset);
+        self
+    }
+
+    /// The value of a column to insert, or to set on update.
+    pub fn value<T>(mut self, column: &str, value: T) -> Self
+    where
+        T: Encode<'q, Driver> + Type<Driver> + Send + 'q,
+    {
+        self.columns.push(column.to_string());
+        self.values.push(SLOT.to_string());
+        self.push(Clause::Values, value)
+    }
+
+    /// Same as [`Query::value`], reads better on updates.
+    pub fn set<T>(self, column: &str, value: T) -> Self
+    where
+        T: Encode<'q, Driver> + Type<Driver> + Send + 'q,
+    {
+        self.value(column, value)
+    }
+
+    /// A column to insert or set to an SQL expression, e.g.
+    /// `current_timestamp`.
+    pub fn value_sql(mut self, column: &str, sql: &str) -> Self {
+        self.columns.push(column.to_string());
+        self.values.push(slots(sql));
+        self.last = Clause::Values;
+        self
+    }
+
+    pub fn set_sql(self, column: &str, sql: &str) -> Self {
+        self.value_sql(column, sql)
+    }
+
+    /// Turns an insert into an upsert on the given unique columns, followed
+    /// by [`Query::do_update`] or [`Query::do_nothing`].
+    pub fn on_conflict(mut self, columns: &[&str]) -> Self {
+        self.conflict = Some(Conflict {
+            columns: columns.iter().map(|column| column.to_string()).collect(),
+            update: Vec::new(),
+        });
+        self
+    }
+
+    /// Overwrites `columns` of the conflicting row with the inserted values.
+    pub fn do_update(mut self, columns: &[&str]) -> Self {
+        if let Some(conflict) = &mut self.conflict {
+            conflict
+                .update
+                .extend(columns.iter().map(|column| column.to_string()));
+        }
+        self
+    }
+
+    /// Keeps the conflicting row as is, the default after
+    /// [`Query::on_conflict`].
+    pub fn do_nothing(mut self) -> Self {
+        if let Some(conflict) = &mut self.conflict {
+            conflict.update.clear();
+        }
+        self
+    }
+
+    pub fn returning(mut self, columns: &[&str]) -> Self {
+        self.returning
+            .extend(columns.iter().map(|column| column.to_string()));
+        self
+    }
+
+    pub fn to_sql(&self) -> String {
+        let mut sql = match self.kind {
+            Kind::Select => {
+                let columns = if self.columns.is_empty() {
+                    "*".to_string()
+                } else {
+                    self.columns.join(", ")
+                };
+                format!("SELECT {} FROM {}", columns, self.table)
+            }
+            Kind::Insert => {
+                let ignore = match (&self.conflict, DRIVER) {
+                    (Some(conflict), config::Driver::Mysql) if conflict.update.is_empty() => {
+                        " IGNORE"
+                    }
+                    _ => "",
+                };
+                if self.columns.is_empty() && DRIVER != config::Driver::Mysql {
+                    format!("INSERT INTO {} DEFAULT VALUES", self.table)
+                } else {
+                    format!(
+                        "INSERT{} INTO {} ({}) VALUES ({})",
+                        ignore,
+                        self.table,
+                        self.columns.join(", "),
+                        self.values.join(", ")
+                    )
+                }
+            }
+            Kind::Update => {
+                let set: Vec<String> = self
+                    .columns
+                    .iter()
+                    .zip(&self.values)
+                    .map(|(column, value)| format!("{} = {}", column, value))
+                    .collect();
+                format!("UPDATE {} SET {}", self.table, set.join(", "))
+            }
+            Kind::Delete => format!("DELETE FROM {}", self.table),
+        };
+
+        for join in &self.joins {
+            sql.push(' ');
+            sql.push_str(join);
+        }
+        if !self.conditions.is_empty() {
+            sql.push_str(" WHERE ");
+            sql.push_str(&self.conditions.join(" AND "));
+        }
+        if let (Kind::Insert, Some(conflict)) = (self.kind, &self.conflict) {
+            sql.push_str(&conflict_clause(conflict));
+        }
+        if !self.order.is_empty() {
+            sql.push_str(" ORDER BY ");
+            sql.push_str(&self.order.join(", "));
+        }
+        if let Some(limit) = self.limit {
+            sql.push_str(&format!(" LIMIT {}", limit));
+        }
+        if let Some(offset) = self.offset {
+            sql.push_str(&format!(" OFFSET {}", offset));
+        }
+        if !self.returning.is_empty() {
+            sql.push_str(" RETURNING ");
+            sql.push_str(&self.returning.join(", "));
+        }
+
+        let mut n = 0;
+        sql.split(SLOT)
+            .enumerate()
+            .map(|(i, part)| {
+                if i == 0 {
+                    part.to_string()
+                } else {
+                    n += 1;
+                    format!("{}{}", placeholder(n), part)
+                }
+            })
+            .collect()
+    }
+
+    pub async fn fetch_all<'e, T, E>(self, db: E) -> Result<Vec<T>, sqlx::Error>
+    where
+        T: for<'r> FromRow<'r, Row> + Send + Unpin,
+        E: Executor<'e, Database = Driver>,
+    {
+        let (sql, args) = self.build();
+        sqlx::query_as_with(&sql, args).fetch_all(db).await
+    }
+
+    pub async fn fetch_one<'e, T, E>(self, db: E) -> Result<T, sqlx::Error>
+    where
+        T: for<'r> FromRow<'r, Row> + Send + Unpin,
+        E: Executor<'e, Database = Driver>,
+    {
+        let (sql, args) = self.build();
+        sqlx::query_as_with(&sql, args).fetch_one(db).await
+    }
+
+    pub async fn fetch_optional<'e, T, E>(self, db: E) -> Result<Option<T>, sqlx::Error>
+    where
+        T: for<'r> FromRow<'r, Row> + Send + Unpin,
+        E: Executor<'e, Database = Driver>,
+    {
+        let (sql, args) = self.build();
+        sqlx::query_as_with(&sql, args).fetch_optional(db).await
+    }
+
+    pub async fn execute<'e, E>(self, db: E) -> Result<QueryResult, sqlx::Error>
+    where
+        E: Executor<'e, Database = Driver>,
+    {
+        let (sql, args) = self.build();
+        sqlx::query_with(&sql, args).execute(db).await
+    }
+
+    fn push<T>(mut self, clause: Clause, value: T) -> Self
+    where
+        T: Encode<'q, Driver> + Type<Driver> + Send + 'q,
+    {
+        self.binds
+            .push((clause, Box::new(move |args: &mut Args<'q>| args.add(value))));
+        self.last = clause;
+        self
+    }
+
+    /// The statement and its parameters, bound clause by clause in the
+    /// order [`Query::to_sql`] writes them.
+    fn build(mut self) -> (String, Args<'q>) {
+        let sql = self.to_sql();
+        let mut args = Args::default();
+        // A stable sort, keeping the order of the binds within a clause.
+        self.binds.sort_by_key(|(clause, _)| *clause);
+        for (_, bind) in self.binds {
+            bind(&mut args);
+        }
+        (sql, args)
+    }
+}
+
+fn conflict_clause(conflict: &Conflict) -> String {
+    match (DRIVER, conflict.update.is_empty()) {
+        (config::Driver::Mysql, true) => String::new(),
+        (config::Driver::Mysql, false) => {
+            let set: Vec<String> = conflict
+                .update
+                .iter()
+                .map(|column| format!("{} = VALUES({})", column, column))
+                .collect();
+            format!(" ON DUPLICATE KEY UPDATE {}", set.join(", "))
+        }
+        (_, true) => format!(" ON CONFLICT ({}) DO NOTHING", conflict.columns.join(", ")),
+        (_, false) => {
+            let set: Vec<String> = conflict
+                .update
+                .iter()
+                .map(|column| format!("{} = EXCLUDED.{}", column, column))
+                .collect();
+            format!(
+                " ON CONFLICT ({}) DO UPDATE SET {}",
+                conflict.columns.join(", "),
+                set.join(", ")
+            )
+        }
+    }
+}
+
+/// Replaces the `?` of a raw fragment with bind slots, skipping quoted
+/// strings and identifiers, and turns `??` into a literal `?`.
+fn slots(fragment: &str) -> String {
+    let mut sql = String::with_capacity(fragment.len());
+    let mut quote = None;
+    let mut chars = fragment.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, quote) {
+            ('\'' | '"', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            ('?', None) if chars.peek() == Some(&'?') => {
+                chars.next();
+            }
+            ('?', None) => {
+                sql.push(SLOT);
+                continue;
+            }
+            _ => {}
+        }
+        sql.push(c);
+    }
+
+    sql
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(not(feature = "mysql"))]
+    fn numbers_parameters_in_statement_order() {
+        let query = Query::select("posts")
+            .where_eq("posts.title", "a")
+            .join("JOIN users ON users.id = posts.user_id AND users.role = ?")
+            .bind("admin")
+            .where_raw("posts.views > ? OR posts.views < ?")
+            .bind(1)
+            .bind(2);
+
+        assert_eq!(
+            query.to_sql(),
+            "SELECT * FROM posts \
+             JOIN users ON users.id = posts.user_id AND users.role = $1 \
+             WHERE posts.title = $2 AND (posts.views > $3 OR posts.views < $4)"
+        );
+    }
+
+    #[test]
+    #[cfg(not(feature = "mysql"))]
+    fn numbers_values_before_conditions() {
+        let query = Query::update("posts")
+            .where_eq("id", 1)
+            .set("title", "a")
+            .set_sql("updated_at", "current_timestamp")
+            .set_sql("views", "views + ?")
+            .bind(1);
+
+        assert_eq!(
+            query.to_sql(),
+            "UPDATE posts SET title = $1, updated_at = current_timestamp, views = views + $2 \
+             WHERE id = $3"
+        );
+    }
+
+    #[test]
+    fn binds_clause_by_clause() {
+        let mut query = Query::update("posts")
+            .where_eq("id", 1)
+            .join("JOIN users ON users.id = ?")
+            .bind(2)
+            .set("title", "a")
+            .where_in("views", [3, 4]);
+
+        query.binds.sort_by_key(|(clause, _)| *clause);
+        let clauses: Vec<Clause> = query.binds.iter().map(|(clause, _)| *clause).collect();
+        assert_eq!(
+            clauses,
+            [
+                Clause::Values,
+                Clause::Joins,
+                Clause::Conditions,
+                Clause::Conditions,
+                Clause::Conditions
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(not(feature = "mysql"))]
+    fn leaves_quoted_question_marks_alone() {
+        let query = Query::select("posts")
+            .where_raw("title = '?' AND \"?\" = ? AND body = 'it''s ?'")
+            .bind("a")
+            .where_raw("body LIKE ??")
+            .where_eq("id", 1);
+
+        assert_eq!(
+            query.to_sql(),
+            "SELECT * FROM posts \
+             WHERE (title = '?' AND \"?\" = $1 AND body = 'it''s ?') AND (body LIKE ?) AND id = $2"
+        );
+    }
+
+    #[test]
+    fn replaces_question_marks_with_slots() {
+        assert_eq!(slots("a = ?"), format!("a = {}", SLOT));
+        assert_eq!(slots("a = ??"), "a = ?");
+        assert_eq!(slots("a = '?' AND b = \"?\""), "a = '?' AND b = \"?\"");
+        assert_eq!(
+            slots("a = 'it''s' AND b = ?"),
+            format!("a = 'it''s' AND b = {}", SLOT)
+        );
+        assert_eq!(
+            slots("a = '\"' AND b = ?"),
+            format!("a = '\"' AND b = {}", SLOT)
+        );
+    }
+
+    #[test]
+    #[cfg(not(feature = "mysql"))]
+    fn upserts() {
+        let query = Query::insert("posts")
+            .value("slug", "a")
+            .value("title", "b")
+            .value_sql("created_at", "current_timestamp")
+            .on_conflict(&["slug"])
+            .do_update(&["title", "created_at"])
+            .returning(&["id", "title"]);
+
+        assert_eq!(
+            query.to_sql(),
+            "INSERT INTO posts (slug, title, created_at) VALUES ($1, $2, current_timestamp) \
+             ON CONFLICT (slug) DO UPDATE SET title = EXCLUDED.title, created_at = EXCLUDED.created_at \
+             RETURNING id, title"
+        );
+
+        let query = Query::insert("posts")
+            .value("slug", "a")
+            .on_conflict(&["slug"])
+            .do_update(&["title"])
+            .do_nothing();
+
+        assert_eq!(
+            query.to_sql(),
+            "INSERT INTO posts (slug) VALUES ($1) ON CONFLICT (slug) DO NOTHING"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "mysql")]
+    fn upserts() {
+        let query = Query::insert("posts")
+            .value("slug", "a")
+            .value("title", "b")
+            .on_conflict(&["slug"])
+            .do_update(&["title"]);
+
+        assert_eq!(
+            query.to_sql(),
+            "INSERT INTO posts (slug, title) VALUES (?, ?) ON DUPLICATE KEY UPDATE title = VALUES(title)"
+        );
+
+        let query = Query::insert("posts")
+            .value("slug", "a")
+            .on_conflict(&["slug"]);
+
+        assert_eq!(query.to_sql(), "INSERT IGNORE INTO posts (slug) VALUES (?)");
+    }
+
+    #[test]
+    #[cfg(not(feature = "mysql"))]
+    fn inserts_default_values() {
+        assert_eq!(
+            Query::insert("posts").returning(&["*"]).to_sql(),
+            "INSERT INTO posts DEFAULT VALUES RETURNING *"
+        );
+    }
+}
@@ -0,0 +1,130 @@
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Weak,
+    },
+    time::Duration,
+};
+
+use sqlx::Pool;
+
+use crate::{Driver, DB};
+
+/// How often replicas are probed, so reads return to a replica that came
+/// back up.
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+tokio::task_local! {
+    static READ_ONLY: bool;
+}
+
+/// Runs `f` with [`DB::get_pool`] returning a replica, so handlers that
+/// only read can be moved off the primary without changing their queries.
+/// The setting is local to the task, so a task `f` spawns reads from the
+/// primary unless its future is wrapped in `read_only` too.
+pub async fn read_only<F: Future>(f: F) -> F::Output {
+    READ_ONLY.scope(true, f).await
+}
+
+pub fn is_read_only() -> bool {
+    READ_ONLY.try_with(|read_only| *read_only).unwrap_or(false)
+}
+
+#[derive(Default)]
+pub(crate) struct Replicas {
+    pub(crate) replicas: Vec<Replica>,
+    next: AtomicUsize,
+}
+
+pub(crate) struct Replica {
+    pool: Pool<Driver>,
+    pub(crate) healthy: AtomicBool,
+}
+
+impl Replicas {
+    pub(crate) fn new(pools: Vec<Pool<Driver>>) -> Self {
+        Replicas {
+            replicas: pools
+                .into_iter()
+                .map(|pool| Replica {
+                    pool,
+                    healthy: AtomicBool::new(true),
+                })
+                .collect(),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.replicas.is_empty()
+    }
+
+    /// The next healthy replica in round-robin order.
+    fn pick(&self) -> Option<&Pool<Driver>> {
+        let len = self.replicas.len();
+        if len == 0 {
+            return None;
+        }
+
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        (0..len)
+            .map(|i| &self.replicas[(start + i) % len])
+            .find(|replica| replica.healthy.load(Ordering::Relaxed))
+            .map(|replica| &replica.pool)
+    }
+}
+
+impl DB {
+    /// The primary pool, for writes and reads that must see them.
+    pub fn writer(&self) -> &Pool<Driver> {
+        &self.pool
+    }
+
+    /// A replica pool, taken in turn among the healthy ones, or the primary
+    /// when there are none.
+    pub fn reader(&self) -> &Pool<Driver> {
+        self.replicas.pick().unwrap_or(&self.pool)
+    }
+
+    /// Probes the replicas in the background, taking those that do not
+    /// answer out of rotation until they do again. Stops once every clone
+    /// of the [`DB`] is dropped.
+    pub fn check_replicas(&self) {
+        if self.replicas.is_empty() {
+            return;
+        }
+
+        let replicas: Weak<Replicas> = Arc::downgrade(&self.replicas);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(CHECK_INTERVAL);
+            // The first tick completes immediately, right after the probe
+            // of `DB::connect`.
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let Some(replicas) = replicas.upgrade() else {
+                    break;
+                };
+                replicas.probe().await;
+            }
+        });
+    }
+}
+
+impl Replicas {
+    pub(crate) async fn probe(&self) {
+        for (i, replica) in self.replicas.iter().enumerate() {
+            let probe = sqlx::query("SELECT 1").execute(&replica.pool);
+            let healthy = matches!(tokio::time::timeout(CHECK_TIMEOUT, probe).await, Ok(Ok(_)));
+            if replica.healthy.swap(healthy, Ordering::Relaxed) != healthy {
+                if healthy {
+                    tracing::info!("Replica {} is back, reading from it again", i);
+                } else {
+                    tracing::warn!("Replica {} is down, reading from the others", i);
+                }
+            }
+        }
+    }
+}
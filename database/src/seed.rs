@@ -0,0 +1,481 @@
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use sqlx::{Connection as _, Executor};
+
+use crate::{driver::placeholder, Args, Connection, DB, DRIVER};
+
+const CREATE_TABLE: &str = match DRIVER {
+    config::Driver::Mysql => {
+        "CREATE TABLE IF NOT EXISTS _seeds (
+            name VARCHAR(255) PRIMARY KEY,
+            checksum TEXT,
+            seeded_at TIMESTAMP NOT NULL DEFAULT current_timestamp
+        )"
+    }
+    _ => {
+        "CREATE TABLE IF NOT EXISTS _seeds (
+            name TEXT PRIMARY KEY,
+            checksum TEXT,
+            seeded_at TIMESTAMPTZ NOT NULL DEFAULT current_timestamp
+        )"
+    }
+};
+
+#[derive(Debug)]
+pub enum SeedError {
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    InvalidFixture {
+        path: PathBuf,
+        message: String,
+    },
+    Duplicate(String),
+    Unknown(String),
+    UnknownDependency {
+        seeder: String,
+        dependency: String,
+    },
+    Cycle(String),
+    Database(sqlx::Error),
+}
+
+impl From<sqlx::Error> for SeedError {
+    fn from(e: sqlx::Error) -> Self {
+        SeedError::Database(e)
+    }
+}
+
+impl std::error::Error for SeedError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SeedError::Io { source, .. } => Some(source),
+            SeedError::Database(source) => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for SeedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SeedError::Io { path, source } => {
+                write!(f, "Failed to read {}: {}", path.display(), source)
+            }
+            SeedError::InvalidFixture { path, message } => {
+                write!(f, "Invalid fixture {}: {}", path.display(), message)
+            }
+            SeedError::Duplicate(name) => write!(f, "Seeder `{}` is defined more than once", name),
+            SeedError::Unknown(name) => write!(f, "Unknown seeder `{}`", name),
+            SeedError::UnknownDependency { seeder, dependency } => write!(
+                f,
+                "Seeder `{}` depends on the unknown seeder `{}`",
+                seeder, dependency
+            ),
+            SeedError::Cycle(name) => {
+                write!(
+                    f,
+                    "Seeder `{}` depends on itself through its dependencies",
+                    name
+                )
+            }
+            SeedError::Database(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// A named batch of data, run once per database inside a transaction and
+/// recorded in the `_seeds` table.
+///
+/// ```ignore
+/// struct Posts;
+///
+/// #[async_trait]
+/// impl Seeder for Posts {
+///     fn name(&self) -> &str {
+///         "posts"
+///     }
+///
+///     async fn run(&self, conn: &mut Connection) -> Result<(), SeedError> {
+///         PostDB::factory().count(20).create(conn).await?;
+///         Ok(())
+///     }
+/// }
+/// ```
+#[async_trait]
+pub trait Seeder: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Names of the seeders that must run before this one.
+    fn depends_on(&self) -> &[&str] {
+        &[]
+    }
+
+    /// A hash of the data, to warn when it changed after it was seeded.
+    fn checksum(&self) -> Option<String> {
+        None
+    }
+
+    async fn run(&self, conn: &mut Connection) -> Result<(), SeedError>;
+}
+
+/// A fixture file of a seed directory.
+///
+/// `<order>_<name>.sql` files run as is, `<order>_<name>.json` files hold an
+/// array of rows, as objects of column values, inserted into the `<name>`
+/// table. The seeder is named `<name>`.
+#[derive(Clone, Debug)]
+pub struct Fixture {
+    name: String,
+    path: PathBuf,
+    data: FixtureData,
+    checksum: String,
+}
+
+#[derive(Clone, Debug)]
+enum FixtureData {
+    Sql(String),
+    Rows(Vec<Map<String, Value>>),
+}
+
+impl Fixture {
+    /// Reads the fixture at `path`, or `None` when it is neither a `.sql`
+    /// nor a `.json` file.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Option<Self>, SeedError> {
+        let path = path.as_ref();
+        let (Some(stem), Some(extension)) = (
+            path.file_stem().and_then(|stem| stem.to_str()),
+            path.extension().and_then(|extension| extension.to_str()),
+        ) else {
+            return Ok(None);
+        };
+        if extension != "sql" && extension != "json" {
+            return Ok(None);
+        }
+
+        let invalid = |message: String| SeedError::InvalidFixture {
+            path: path.into(),
+            message,
+        };
+        let name = match stem.split_once('_') {
+            Some((order, name)) if order.chars().all(|c| c.is_ascii_digit()) => name,
+            _ => stem,
+        };
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(invalid(format!("`{}` is not a valid table name", name)));
+        }
+
+        let text = std::fs::read_to_string(path).map_err(|source| SeedError::Io {
+            path: path.into(),
+            source,
+        })?;
+        let data = if extension == "sql" {
+            FixtureData::Sql(text.clone())
+        } else {
+            let rows: Vec<Map<String, Value>> =
+                serde_json::from_str(&text).map_err(|e| invalid(e.to_string()))?;
+            if let Some(column) = rows.iter().flat_map(|row| row.keys()).find(|column| {
+                !column
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_')
+            }) {
+                return Err(invalid(format!("`{}` is not a valid column name", column)));
+            }
+            FixtureData::Rows(rows)
+        };
+
+        Ok(Some(Fixture {
+            name: name.to_string(),
+            path: path.into(),
+            data,
+            checksum: Sha256::digest(text.as_bytes())
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect(),
+        }))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[async_trait]
+impl Seeder for Fixture {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn checksum(&self) -> Option<String> {
+        Some(self.checksum.clone())
+    }
+
+    async fn run(&self, conn: &mut Connection) -> Result<(), SeedError> {
+        match &self.data {
+            FixtureData::Sql(sql) => {
+                conn.execute(sql.as_str()).await?;
+            }
+            FixtureData::Rows(rows) => {
+                for row in rows {
+                    insert_row(conn, &self.name, row).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Postgres will not cast text parameters to the column types, so the row
+/// is expanded from JSON on the server instead.
+async fn insert_row(
+    conn: &mut Connection,
+    table: &str,
+    row: &Map<String, Value>,
+) -> Result<(), sqlx::Error> {
+    let columns = row
+        .keys()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    if DRIVER == config::Driver::Postgres {
+        let sql = format!(
+            "INSERT INTO {table} ({columns}) SELECT {columns} FROM json_populate_record(NULL::{table}, $1::json)"
+        );
+        sqlx::query(&sql)
+            .bind(Value::Object(row.clone()).to_string())
+            .execute(conn)
+            .await?;
+        return Ok(());
+    }
+
+    let values = (1..=row.len()).map(placeholder).collect::<Vec<_>>();
+    let sql = format!(
+        "INSERT INTO {} ({}) VALUES ({})",
+        table,
+        columns,
+        values.join(", ")
+    );
+    let mut args = Args::default();
+    for value in row.values() {
+        bind_json(&mut args, value);
+    }
+    sqlx::query_with(&sql, args).execute(conn).await?;
+    Ok(())
+}
+
+fn bind_json<'q>(args: &mut Args<'q>, value: &'q Value) {
+    use sqlx::Arguments;
+
+    match value {
+        Value::Null => args.add(None::<String>),
+        Value::Bool(value) => args.add(*value),
+        Value::Number(number) => match number.as_i64() {
+            Some(number) => args.add(number),
+            None => args.add(number.as_f64()),
+        },
+        Value::String(value) => args.add(value.as_str()),
+        value => args.add(value.to_string()),
+    }
+}
+
+/// The seeders of an application, run in dependency order.
+///
+/// Seeders already recorded in `_seeds` are skipped, so running them again
+/// only adds the new ones. [`Seeds::force`] runs them all again.
+#[derive(Default)]
+pub struct Seeds {
+    seeders: Vec<Box<dyn Seeder>>,
+    force: bool,
+}
+
+impl Seeds {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the fixtures of `dir` in file name order. A missing directory
+    /// has no fixtures.
+    pub fn from_dir(dir: impl AsRef<Path>) -> Result<Self, SeedError> {
+        let mut seeds = Self::new();
+        seeds.set_fixtures(dir)?;
+        Ok(seeds)
+    }
+
+    pub fn seeder(mut self, seeder: impl Seeder + 'static) -> Self {
+        self.seeders.push(Box::new(seeder));
+        self
+    }
+
+    pub fn set_seeder(&mut self, seeder: impl Seeder + 'static) -> &mut Self {
+        self.seeders.push(Box::new(seeder));
+        self
+    }
+
+    pub fn fixtures(mut self, dir: impl AsRef<Path>) -> Result<Self, SeedError> {
+        self.set_fixtures(dir)?;
+        Ok(self)
+    }
+
+    pub fn set_fixtures(&mut self, dir: impl AsRef<Path>) -> Result<&mut Self, SeedError> {
+        let dir = dir.as_ref();
+        if !dir.exists() {
+            return Ok(self);
+        }
+        let io = |source| SeedError::Io {
+            path: dir.into(),
+            source,
+        };
+
+        let mut paths = std::fs::read_dir(dir)
+            .map_err(io)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(io)?;
+        paths.sort();
+        for path in paths {
+            if let Some(fixture) = Fixture::from_file(&path)? {
+                self.seeders.push(Box::new(fixture));
+            }
+        }
+        Ok(self)
+    }
+
+    /// Runs every seeder again, even those already recorded.
+    pub fn force(mut self, enabled: bool) -> Self {
+        self.force = enabled;
+        self
+    }
+
+    pub fn set_force(&mut self, enabled: bool) -> &mut Self {
+        self.force = enabled;
+        self
+    }
+
+    /// The names of every seeder, in the order they run.
+    pub fn names(&self) -> Result<Vec<&str>, SeedError> {
+        Ok(self.order(&[])?.into_iter().map(|s| s.name()).collect())
+    }
+
+    /// Runs the pending seeders, returning the names of those that ran.
+    pub async fn run(&self, db: &DB) -> Result<Vec<&str>, SeedError> {
+        self.run_only(db, &[]).await
+    }
+
+    /// Runs the pending seeders among `names` and their dependencies, or
+    /// every seeder when `names` is empty.
+    pub async fn run_only(&self, db: &DB, names: &[&str]) -> Result<Vec<&str>, SeedError> {
+        let order = self.order(names)?;
+        let mut conn = db.writer().acquire().await?;
+        conn.execute(CREATE_TABLE).await?;
+
+        let seeded: Vec<(String, Option<String>)> =
+            sqlx::query_as("SELECT name, checksum FROM _seeds")
+                .fetch_all(&mut *conn)
+                .await?;
+
+        let mut done = Vec::new();
+        for seeder in order {
+            let name = seeder.name();
+            let checksum = seeder.checksum();
+            if let Some((_, previous)) = seeded.iter().find(|(seeded, _)| seeded == name) {
+                if !self.force {
+                    if checksum.is_some() && *previous != checksum {
+                        tracing::warn!(
+                            "Seeder {} changed after it was seeded, force it to seed it again",
+                            name
+                        );
+                    }
+                    continue;
+                }
+            }
+
+            let mut tx = conn.begin().await?;
+            seeder.run(&mut tx).await?;
+            let sql = format!("DELETE FROM _seeds WHERE name = {}", placeholder(1));
+            sqlx::query(&sql).bind(name).execute(&mut *tx).await?;
+            let sql = format!(
+                "INSERT INTO _seeds (name, checksum) VALUES ({}, {})",
+                placeholder(1),
+                placeholder(2)
+            );
+            sqlx::query(&sql)
+                .bind(name)
+                .bind(checksum)
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+
+            tracing::info!("Seeded {}", name);
+            done.push(name);
+        }
+
+        Ok(done)
+    }
+
+    /// The seeders to run for `names` with their dependencies first, keeping
+    /// the order they were added in otherwise.
+    fn order(&self, names: &[&str]) -> Result<Vec<&dyn Seeder>, SeedError> {
+        for (i, seeder) in self.seeders.iter().enumerate() {
+            if self.seeders[..i].iter().any(|s| s.name() == seeder.name()) {
+                return Err(SeedError::Duplicate(seeder.name().to_string()));
+            }
+        }
+
+        let roots = if names.is_empty() {
+            self.seeders.iter().map(|s| s.name()).collect()
+        } else {
+            names.to_vec()
+        };
+
+        let mut order = Vec::new();
+        let mut visiting = Vec::new();
+        for name in roots {
+            let seeder = self
+                .find(name)
+                .ok_or_else(|| SeedError::Unknown(name.to_string()))?;
+            self.visit(seeder, &mut visiting, &mut order)?;
+        }
+        Ok(order)
+    }
+
+    fn visit<'a>(
+        &'a self,
+        seeder: &'a dyn Seeder,
+        visiting: &mut Vec<&'a str>,
+        order: &mut Vec<&'a dyn Seeder>,
+    ) -> Result<(), SeedError> {
+        if order.iter().any(|s| s.name() == seeder.name()) {
+            return Ok(());
+        }
+        if visiting.contains(&seeder.name()) {
+            return Err(SeedError::Cycle(seeder.name().to_string()));
+        }
+
+        visiting.push(seeder.name());
+        for dependency in seeder.depends_on() {
+            let dependency = self
+                .find(dependency)
+                .ok_or_else(|| SeedError::UnknownDependency {
+                    seeder: seeder.name().to_string(),
+                    dependency: dependency.to_string(),
+                })?;
+            self.visit(dependency, visiting, order)?;
+        }
+        visiting.pop();
+
+        order.push(seeder);
+        Ok(())
+    }
+
+    fn find(&self, name: &str) -> Option<&dyn Seeder> {
+        self.seeders
+            .iter()
+            .find(|s| s.name() == name)
+            .map(|s| s.as_ref())
+    }
+}
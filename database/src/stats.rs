@@ -0,0 +1,144 @@
+use std::{cell::RefCell, collections::HashMap, fmt, future::Future, time::Duration};
+
+use tracing::{
+    field::{Field, Visit},
+    Event, Subscriber,
+};
+use tracing_subscriber::layer::{Context, Layer};
+
+/// The target sqlx logs statements under.
+pub const QUERY_TARGET: &str = "sqlx::query";
+
+tokio::task_local! {
+    static STATS: RefCell<QueryStats>;
+}
+
+/// The statements run while [`track`]ing a future.
+#[derive(Clone, Debug, Default)]
+pub struct QueryStats {
+    pub count: usize,
+    /// Time spent waiting on the database, in total.
+    pub elapsed: Duration,
+    /// Statements over `database.slow_query_threshold`.
+    pub slow: usize,
+    pub rows_returned: u64,
+    pub rows_affected: u64,
+    /// Runs of each statement, keyed by its SQL without the bound values.
+    statements: HashMap<String, usize>,
+}
+
+impl QueryStats {
+    /// The `SELECT`s that ran at least `threshold` times, as a loop running
+    /// one query per item would, with how many times they ran.
+    pub fn repeated(&self, threshold: usize) -> Vec<(&str, usize)> {
+        let mut repeated: Vec<(&str, usize)> = self
+            .statements
+            .iter()
+            .filter(|(sql, runs)| {
+                threshold > 0
+                    && **runs >= threshold
+                    && sql
+                        .trim_start()
+                        .get(..6)
+                        .is_some_and(|verb| verb.eq_ignore_ascii_case("select"))
+            })
+            .map(|(sql, runs)| (sql.as_str(), *runs))
+            .collect();
+        repeated.sort_by_key(|(_, runs)| std::cmp::Reverse(*runs));
+        repeated
+    }
+}
+
+/// Runs `f` and collects the statements it runs, as seen by [`QueryLayer`].
+/// Statements of the tasks `f` spawns are left out of the counts, so work
+/// moved to the background does not show up as N+1 queries.
+pub async fn track<F: Future>(f: F) -> (F::Output, QueryStats) {
+    STATS
+        .scope(RefCell::new(QueryStats::default()), async {
+            let output = f.await;
+            (output, STATS.with(|stats| stats.take()))
+        })
+        .await
+}
+
+/// Adds the statements logged by sqlx to the [`QueryStats`] of the task
+/// running them. Give it a filter letting [`QUERY_TARGET`] through at every
+/// level, so statements are counted even when the log level hides them:
+///
+/// ```ignore
+/// tracing_subscriber::registry()
+///     .with(QueryLayer.with_filter(Targets::new().with_target(QUERY_TARGET, Level::TRACE)))
+///     .with(fmt_layer.with_filter(env_filter))
+///     .init();
+/// ```
+pub struct QueryLayer;
+
+impl<S: Subscriber> Layer<S> for QueryLayer {
+    fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+        if event.metadata().target() != QUERY_TARGET {
+            return;
+        }
+
+        let _ = STATS.try_with(|stats| {
+            let mut statement = Statement::default();
+            event.record(&mut statement);
+
+            let mut stats = stats.borrow_mut();
+            stats.count += 1;
+            stats.elapsed += Duration::from_secs_f64(statement.elapsed_secs);
+            stats.slow += usize::from(statement.slow);
+            stats.rows_returned += statement.rows_returned;
+            stats.rows_affected += statement.rows_affected;
+            // The full statement is only logged when longer than the
+            // summary, and formatted over several lines.
+            let sql = if statement.sql.trim().is_empty() {
+                &statement.summary
+            } else {
+                &statement.sql
+            };
+            let sql = sql.split_whitespace().collect::<Vec<_>>().join(" ");
+            *stats.statements.entry(sql).or_default() += 1;
+        });
+    }
+}
+
+#[derive(Default)]
+struct Statement {
+    summary: String,
+    sql: String,
+    rows_returned: u64,
+    rows_affected: u64,
+    elapsed_secs: f64,
+    slow: bool,
+}
+
+impl Visit for Statement {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "summary" => self.summary = value.to_string(),
+            "db.statement" => self.sql = value.to_string(),
+            _ => {}
+        }
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        match field.name() {
+            "rows_returned" => self.rows_returned = value,
+            "rows_affected" => self.rows_affected = value,
+            _ => {}
+        }
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        if field.name() == "elapsed_secs" {
+            self.elapsed_secs = value;
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, _: &dyn fmt::Debug) {
+        // Only slow statements carry their threshold.
+        if field.name() == "slow_threshold" {
+            self.slow = true;
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use std::{future::Future, pin::Pin};
+
+use sqlx::Connection as _;
+
+use crate::{Connection, Driver, DB};
+
+/// A transaction that owns its pooled connection.
+pub type Transaction = sqlx::Transaction<'static, Driver>;
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+impl DB {
+    pub async fn begin(&self) -> Result<Transaction, sqlx::Error> {
+        self.pool.begin().await
+    }
+
+    /// Runs `f` in a transaction that is committed when it returns `Ok` and
+    /// rolled back when it returns `Err`.
+    ///
+    /// ```ignore
+    /// db.transaction(|tx| Box::pin(async move {
+    ///     Query::insert("posts")
+    ///         .value("title", title)
+    ///         .execute(&mut *tx)
+    ///         .await?;
+    ///     Ok::<_, sqlx::Error>(())
+    /// }))
+    /// .await?;
+    /// ```
+    pub async fn transaction<F, T, E>(&self, f: F) -> Result<T, E>
+    where
+        F: for<'c> FnOnce(&'c mut Connection) -> BoxFuture<'c, Result<T, E>>,
+        E: From<sqlx::Error>,
+    {
+        run(self.begin().await?, f).await
+    }
+}
+
+/// Runs `f` in a savepoint of the transaction `conn` is in, so an `Err`
+/// only undoes what `f` did. Outside of a transaction it behaves like
+/// [`DB::transaction`].
+pub async fn savepoint<F, T, E>(conn: &mut Connection, f: F) -> Result<T, E>
+where
+    F: for<'c> FnOnce(&'c mut Connection) -> BoxFuture<'c, Result<T, E>>,
+    E: From<sqlx::Error>,
+{
+    run(conn.begin().await?, f).await
+}
+
+async fn run<F, T, E>(mut tx: sqlx::Transaction<'_, Driver>, f: F) -> Result<T, E>
+where
+    F: for<'c> FnOnce(&'c mut Connection) -> BoxFuture<'c, Result<T, E>>,
+    E: From<sqlx::Error>,
+{
+    match f(&mut tx).await {
+        Ok(value) => {
+            tx.commit().await?;
+            Ok(value)
+        }
+        Err(e) => {
+            // The error of `f` explains more than a failed rollback would,
+            // and the connection is closed when the rollback fails anyway.
+            if let Err(rollback) = tx.rollback().await {
+                tracing::warn!("Failed to roll back transaction: {}", rollback);
+            }
+            Err(e)
+        }
+    }
+}
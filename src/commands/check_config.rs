@@ -0,0 +1,25 @@
+use database::DB;
+use system::{Error, Result, System};
+use tokio::runtime::Builder;
+
+pub fn run(system: &System, args: &[String]) -> Result<()> {
+    let config = system.check_config()?;
+    println!("Config is valid (environment: {})", config.app.environment);
+
+    if args.iter().any(|arg| arg == "--db") {
+        Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(Error::Runtime)?
+            .block_on(async {
+                let db = DB::connect(&config.database)
+                    .await
+                    .map_err(Error::Connect)?;
+                sqlx::query("SELECT 1").execute(db.get_pool()).await?;
+                Ok::<_, Error>(())
+            })?;
+        println!("Connected to the database");
+    }
+
+    Ok(())
+}
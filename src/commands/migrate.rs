@@ -0,0 +1,57 @@
+use database::{Migrator, DB};
+use system::{Error, Result, System};
+use tokio::runtime::Builder;
+
+pub fn run(system: &System, args: &[String]) -> Result<()> {
+    let config = system.check_database_config()?;
+    let migrator = Migrator::from_dir(&config.database.migrations)?;
+
+    Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(Error::Runtime)?
+        .block_on(async {
+            let db = DB::connect(&config.database)
+                .await
+                .map_err(Error::Connect)?;
+
+            match args.first().map(String::as_str) {
+                None | Some("up") => {
+                    let applied = migrator.up(&db).await?;
+                    for migration in &applied {
+                        println!("Applied {}_{}", migration.version, migration.name);
+                    }
+                    if applied.is_empty() {
+                        println!("Nothing to migrate");
+                    }
+                }
+                Some("down") => {
+                    let steps = match args.get(1) {
+                        Some(steps) => steps.parse().map_err(|_| {
+                            Error::Usage(format!("Invalid number of steps `{steps}`"))
+                        })?,
+                        None => 1,
+                    };
+                    for migration in migrator.down(&db, steps).await? {
+                        println!("Reverted {}_{}", migration.version, migration.name);
+                    }
+                }
+                Some("status") => {
+                    for status in migrator.status(&db).await? {
+                        let applied_at = status
+                            .applied_at
+                            .map(|at| at.format("%Y-%m-%d %H:%M:%S").to_string())
+                            .unwrap_or_else(|| "pending".to_string());
+                        println!("{:<20} {:<40} {}", status.version, status.name, applied_at);
+                    }
+                }
+                Some(command) => {
+                    return Err(Error::Usage(format!(
+                        "Unknown migrate command `{command}`, expected up, down or status"
+                    )));
+                }
+            }
+
+            Ok(())
+        })
+}
@@ -0,0 +1,33 @@
+pub mod check_config;
+pub mod migrate;
+pub mod seed;
+
+use system::{Error, Result, System};
+
+const USAGE: &str = "Usage: jaya [COMMAND]
+
+Commands:
+  serve                 Run the server (default)
+  check-config [--db]   Validate the config, and with --db test the database connection
+  migrate [up]          Apply pending migrations
+  migrate down [N]      Revert the last N migrations (default 1)
+  migrate status        List migrations and when they were applied
+  seed [NAME...]        Run the pending seeders, or NAME and its dependencies
+  seed --force          Run the seeders again, even those already run
+  seed list             List the seeders in the order they run";
+
+pub fn run(system: System, args: &[String]) -> Result<()> {
+    match args.first().map(String::as_str) {
+        None | Some("serve") => system.run(),
+        Some("check-config") => check_config::run(&system, &args[1..]),
+        Some("migrate") => migrate::run(&system, &args[1..]),
+        Some("seed") => seed::run(&system, &args[1..]),
+        Some("help" | "--help" | "-h") => {
+            println!("{USAGE}");
+            Ok(())
+        }
+        Some(command) => Err(Error::Usage(format!(
+            "Unknown command `{command}`\n\n{USAGE}"
+        ))),
+    }
+}
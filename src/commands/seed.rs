@@ -0,0 +1,42 @@
+use database::DB;
+use system::{Error, Result, System};
+use tokio::runtime::Builder;
+
+pub fn run(system: &System, args: &[String]) -> Result<()> {
+    let config = system.check_database_config()?;
+    let force = args.iter().any(|arg| arg == "--force");
+    let names: Vec<&str> = args
+        .iter()
+        .map(String::as_str)
+        .filter(|arg| *arg != "--force")
+        .collect();
+
+    let seeds = crate::seeds::setup(&config)?.force(force);
+
+    if names.first() == Some(&"list") {
+        for name in seeds.names()? {
+            println!("{name}");
+        }
+        return Ok(());
+    }
+
+    Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(Error::Runtime)?
+        .block_on(async {
+            let db = DB::connect(&config.database)
+                .await
+                .map_err(Error::Connect)?;
+
+            let seeded = seeds.run_only(&db, &names).await?;
+            for name in &seeded {
+                println!("Seeded {name}");
+            }
+            if seeded.is_empty() {
+                println!("Nothing to seed");
+            }
+
+            Ok(())
+        })
+}
@@ -1,4 +1,5 @@
 use askama::Template;
+use database::{PageRequest, Repository};
 use system::{extract::State, response::IntoResponse, AppState, Response, TemplateUtils};
 
 use crate::data::post::{Post, PostDB};
@@ -14,9 +15,16 @@ impl TemplateUtils for Post {}
 pub async fn index(State(state): State<AppState>) -> Response<impl IntoResponse> {
     let db = &state.db;
 
-    let posts = PostDB::all(db.get_pool()).await?;
+    let page = PostDB::paginate(
+        db.get_pool(),
+        &PageRequest {
+            per_page: 10,
+            ..PageRequest::default()
+        },
+    )
+    .await?;
 
     Ok(state.render(IndexTemplate {
-        posts: posts.iter().map(|f| f.into()).collect(),
+        posts: page.items.iter().map(|f| f.into()).collect(),
     }))
 }
@@ -1,13 +1,15 @@
 use askama::Template;
+use database::{notify, Driver, Page, Repository, DRIVER};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use system::{
     extract::{Path, State},
+    http::StatusCode,
     response::IntoResponse,
-    AppState, Json, Response,
+    sse, AppState, Json, Pagination, Response, Tx,
 };
 
-use crate::data::post::{Post, PostDB};
+use crate::data::post::{Post, PostDB, PostEvent, CHANNEL};
 
 #[derive(Deserialize, Serialize)]
 pub struct CreatePayload {
@@ -18,19 +20,33 @@ pub struct CreatePayload {
 #[derive(Template)]
 #[template(path = "pages/post/index.html")]
 struct PostTemplate {
-    posts: Vec<Post>,
+    page: Page<Post>,
 }
 
-pub async fn index(State(state): State<AppState>) -> Response<impl IntoResponse> {
+pub async fn index(
+    State(state): State<AppState>,
+    Pagination(request): Pagination,
+) -> Response<impl IntoResponse> {
     let db = &state.db;
 
-    let posts = PostDB::all(db.get_pool()).await?;
+    let page = PostDB::paginate(db.get_pool(), &request).await?;
 
     Ok(state.render(PostTemplate {
-        posts: posts.iter().map(|f| f.into()).collect(),
+        page: page.map(|f| f.into()),
     }))
 }
 
+pub async fn list(
+    State(state): State<AppState>,
+    Pagination(request): Pagination,
+) -> Response<impl IntoResponse> {
+    let db = &state.db;
+
+    let page = PostDB::paginate(db.get_pool(), &request).await?;
+
+    Ok(Json(page.map(Post::from)))
+}
+
 #[derive(Template)]
 #[template(path = "pages/post/show.html")]
 struct ShowPostTemplate {
@@ -70,18 +86,19 @@ pub async fn edit(
     Ok(state.render(EditPostTemplate { post: post.into() }))
 }
 
-pub async fn save(
-    State(state): State<AppState>,
-    Json(payload): Json<CreatePayload>,
-) -> Response<impl IntoResponse> {
-    let db = &state.db;
-
-    let post = PostDB::insert(
-        db.get_pool(),
-        PostDB {
-            title: Some(payload.title),
-            body: Some(payload.body),
-            ..PostDB::default()
+pub async fn save(mut tx: Tx, Json(payload): Json<CreatePayload>) -> Response<impl IntoResponse> {
+    let post = PostDB {
+        title: Some(payload.title),
+        body: Some(payload.body),
+        ..PostDB::default()
+    }
+    .insert(&mut *tx)
+    .await?;
+    // Only sent once the transaction commits.
+    announce(
+        &mut *tx,
+        &PostEvent::Created {
+            post: (&post).into(),
         },
     )
     .await?;
@@ -92,20 +109,23 @@ pub async fn save(
 }
 
 pub async fn update(
-    State(state): State<AppState>,
+    mut tx: Tx,
     Path(id): Path<i64>,
     Json(payload): Json<CreatePayload>,
 ) -> Response<impl IntoResponse> {
-    let db = &state.db;
-
-    let post = PostDB::update(
-        db.get_pool(),
-        PostDB {
-            title: Some(payload.title),
-            body: Some(payload.body),
-            ..PostDB::default()
-        },
+    let post = PostDB {
         id,
+        title: Some(payload.title),
+        body: Some(payload.body),
+        ..PostDB::default()
+    }
+    .update(&mut *tx)
+    .await?;
+    announce(
+        &mut *tx,
+        &PostEvent::Updated {
+            post: (&post).into(),
+        },
     )
     .await?;
 
@@ -114,15 +134,48 @@ pub async fn update(
     })))
 }
 
-pub async fn delete(
-    State(state): State<AppState>,
-    Path(id): Path<i64>,
-) -> Response<impl IntoResponse> {
-    let db = &state.db;
+pub async fn delete(mut tx: Tx, Path(id): Path<i64>) -> Response<impl IntoResponse> {
+    let post = PostDB::delete(&mut *tx, id).await?;
+    announce(&mut *tx, &PostEvent::Deleted { id }).await?;
+
+    Ok(Json(json!({
+        "post": post,
+    })))
+}
 
-    let post = PostDB::delete(db.get_pool(), id).await?;
+pub async fn restore(mut tx: Tx, Path(id): Path<i64>) -> Response<impl IntoResponse> {
+    let post = PostDB::restore(&mut *tx, id).await?;
+    announce(
+        &mut *tx,
+        &PostEvent::Restored {
+            post: (&post).into(),
+        },
+    )
+    .await?;
 
     Ok(Json(json!({
         "post": post,
     })))
 }
+
+/// Streams the [`PostEvent`]s of every worker and process to the browser.
+pub async fn events(State(state): State<AppState>) -> Response<impl IntoResponse> {
+    // `204` tells the browser not to reconnect.
+    if DRIVER != config::Driver::Postgres {
+        return Ok(StatusCode::NO_CONTENT.into_response());
+    }
+    Ok(sse(state.db.subscribe::<PostEvent>(CHANNEL).await?).into_response())
+}
+
+/// Sends `event` to the pages showing posts. Only Postgres has
+/// notifications, and without them the pages just miss the live updates,
+/// so writes go on without sending anything on the other databases.
+async fn announce<'e, E>(db: E, event: &PostEvent) -> Result<(), sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = Driver>,
+{
+    if DRIVER != config::Driver::Postgres {
+        return Ok(());
+    }
+    notify(db, CHANNEL, event).await
+}
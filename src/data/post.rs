@@ -1,64 +1,58 @@
 use chrono::{DateTime, Utc};
+use database::{Factory, Model};
+use fake::{
+    faker::lorem::en::{Paragraphs, Sentence},
+    Fake,
+};
 use serde::{Deserialize, Serialize};
 
-use sqlx::{FromRow, Pool, Postgres};
-use system::{Error, Result};
+use sqlx::FromRow;
 
-#[derive(Serialize, Deserialize, FromRow, Default)]
+#[derive(Serialize, Deserialize, FromRow, Model, Default)]
+#[model(table = "posts", timestamps, soft_delete, audit)]
 pub struct PostDB {
+    #[model(primary_key, sortable)]
     pub id: i64,
+    #[model(sortable, filterable)]
     pub title: Option<String>,
     pub body: Option<String>,
+    #[model(sortable)]
     pub created_at: Option<DateTime<Utc>>,
     pub updated_at: Option<DateTime<Utc>>,
+    pub deleted_at: Option<DateTime<Utc>>,
 }
 
 impl PostDB {
-    pub async fn all(pool: &Pool<Postgres>) -> Result<Vec<Self>> {
-        sqlx::query_as("SELECT * FROM posts LIMIT 10")
-            .fetch_all(pool)
-            .await
-            .map_err(|e| Error::Database(e))
-    }
-
-    pub async fn find(pool: &Pool<Postgres>, id: i64) -> Result<Self> {
-        sqlx::query_as("SELECT * FROM posts WHERE id = $1")
-            .bind(id)
-            .fetch_one(pool)
-            .await
-            .map_err(|e| Error::Database(e))
-    }
-
-    pub async fn insert(pool: &Pool<Postgres>, payload: PostDB) -> Result<Self> {
-        sqlx::query_as(
-            "INSERT INTO posts (title, body, created_at, updated_at) VALUES ($1, $2, current_timestamp, current_timestamp) RETURNING *",
-        )
-        .bind(payload.title)
-        .bind(payload.body)
-        .fetch_one(pool)
-        .await
-        .map_err(|e| Error::Database(e))
+    /// Posts with random lorem ipsum titles and bodies.
+    pub fn factory() -> Factory<PostDB> {
+        Factory::new(|_| PostDB {
+            title: Some(Sentence(3..8).fake()),
+            body: Some(Paragraphs(2..5).fake::<Vec<String>>().join("\n\n")),
+            ..Default::default()
+        })
     }
+}
 
-    pub async fn update(pool: &Pool<Postgres>, payload: PostDB, id: i64) -> Result<Self> {
-        sqlx::query_as(
-            "UPDATE posts SET title = $1, body = $2, updated_at = current_timestamp WHERE id = $3 RETURNING *",
-        )
-        .bind(payload.title)
-        .bind(payload.body)
-        .bind(id)
-        .fetch_one(pool)
-        .await
-        .map_err(|e| Error::Database(e))
-    }
+/// The channel [`PostEvent`]s are sent on.
+pub const CHANNEL: &str = "posts";
 
-    pub async fn delete(pool: &Pool<Postgres>, id: i64) -> Result<Self> {
-        sqlx::query_as("DELETE FROM posts WHERE id = $1 RETURNING *")
-            .bind(id)
-            .fetch_one(pool)
-            .await
-            .map_err(|e| Error::Database(e))
-    }
+/// Sent whenever a post changes, so pages showing it can update.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PostEvent {
+    Created {
+        post: Post,
+    },
+    Updated {
+        post: Post,
+    },
+    Deleted {
+        id: i64,
+    },
+    /// A deleted post was brought back.
+    Restored {
+        post: Post,
+    },
 }
 
 #[derive(Serialize, Deserialize)]
@@ -1,11 +1,21 @@
+mod commands;
 mod controllers;
 mod data;
 mod routes;
+mod seeds;
 
-use system::System;
+use system::{Error, System};
 
 fn main() {
-    if let Err(e) = System::with_router(routes::setup()).prefork(0).run() {
-        panic!("{e}");
+    let system = System::with_router(routes::setup()).config_path("config/app.toml");
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    if let Err(e) = commands::run(system, &args) {
+        eprintln!("{e}");
+        // Everything was dropped on the way up, so exiting skips nothing.
+        std::process::exit(match e {
+            Error::Usage(_) => 2,
+            _ => 1,
+        });
     }
 }
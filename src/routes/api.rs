@@ -1,13 +1,18 @@
 use serde_json::json;
-use system::{routing::get, Json, Router};
+use system::{middleware::from_fn, read_only_layer, routing::get, Json, Router};
+
+use crate::controllers::post;
 
 pub fn router() -> Router {
-    Router::new().route(
-        "/",
-        get(|| async {
-            Json(json!({
-                "message": "Hello from the API!"
-            }))
-        }),
-    )
+    Router::new()
+        .route(
+            "/",
+            get(|| async {
+                Json(json!({
+                    "message": "Hello from the API!"
+                }))
+            }),
+        )
+        .route("/posts", get(post::list))
+        .route_layer(from_fn(read_only_layer))
 }
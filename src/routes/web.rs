@@ -1,5 +1,7 @@
 use system::{
-    routing::{delete, get},
+    middleware::from_fn,
+    read_only_layer,
+    routing::{delete, get, post as post_route},
     Router,
 };
 
@@ -8,13 +10,18 @@ use crate::controllers::{home, post, user};
 pub fn router() -> Router {
     Router::new()
         .route("/", get(home::index))
+        // The list pages only read, so they are served by the replicas.
+        .route_layer(from_fn(read_only_layer))
         .nest(
             "/post",
             Router::new()
                 .route("/", get(post::index))
+                .route_layer(from_fn(read_only_layer))
                 .route("/create", get(post::create).post(post::save))
                 .route("/:id/edit", get(post::edit).put(post::update))
                 .route("/:id/delete", delete(post::delete))
+                .route("/:id/restore", post_route(post::restore))
+                .route("/events", get(post::events))
                 .route("/:id", get(post::show)),
         )
         .route("/user", get(user::index))
@@ -0,0 +1,9 @@
+mod post;
+
+use config::Config;
+use database::{SeedError, Seeds};
+
+/// The fixtures of `database.seeds`, then the seeders defined in code.
+pub fn setup(config: &Config) -> Result<Seeds, SeedError> {
+    Ok(Seeds::from_dir(&config.database.seeds)?.seeder(post::FakePosts))
+}
@@ -0,0 +1,22 @@
+use database::{async_trait, Connection, SeedError, Seeder};
+
+use crate::data::post::PostDB;
+
+/// Demo posts to page through, after the hand-written ones.
+pub struct FakePosts;
+
+#[async_trait]
+impl Seeder for FakePosts {
+    fn name(&self) -> &str {
+        "fake_posts"
+    }
+
+    fn depends_on(&self) -> &[&str] {
+        &["posts"]
+    }
+
+    async fn run(&self, conn: &mut Connection) -> Result<(), SeedError> {
+        PostDB::factory().count(50).create(conn).await?;
+        Ok(())
+    }
+}
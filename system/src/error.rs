@@ -11,10 +11,32 @@ use axum::{
 pub enum Error {
     Http(axum::Error),
     Database(sqlx::Error),
-    FailedToStartServer,
+    Config(config::Error),
+    Migration(database::MigrateError),
+    Seed(database::SeedError),
+    /// Binding or taking over the listening sockets failed.
+    Bind(std::io::Error),
+    /// The bound sockets could not be duplicated or set up for accepting.
+    Listen(std::io::Error),
+    /// The async runtime or its signal handlers could not be set up.
+    Runtime(std::io::Error),
+    /// The database could not be reached, even after retrying.
+    Connect(sqlx::Error),
+    /// The master process failed to fork or supervise the workers.
+    Process(std::io::Error),
+    /// Accepting connections failed while serving.
+    Serve(hyper::Error),
+    /// The TLS certificate or private key could not be loaded.
+    Tls(std::io::Error),
     TemplateError(askama::Error),
     Panic(String),
+    /// A route or handler is wired up wrongly, such as a [`Tx`](crate::Tx)
+    /// extracted without the transaction layer. Answered with a `500`.
+    Misconfigured(String),
+    /// The command line was not understood, the message says why.
+    Usage(String),
     PageNotFound,
+    BadRequest(String),
 }
 
 impl From<axum::Error> for Error {
@@ -23,16 +45,83 @@ impl From<axum::Error> for Error {
     }
 }
 
-impl std::error::Error for Error {}
+impl From<sqlx::Error> for Error {
+    fn from(e: sqlx::Error) -> Self {
+        Error::Database(e)
+    }
+}
+
+impl From<database::PageError> for Error {
+    fn from(e: database::PageError) -> Self {
+        match e {
+            database::PageError::Database(e) => Error::Database(e),
+            e => Error::BadRequest(e.to_string()),
+        }
+    }
+}
+
+impl From<config::Error> for Error {
+    fn from(e: config::Error) -> Self {
+        Error::Config(e)
+    }
+}
+
+impl From<database::MigrateError> for Error {
+    fn from(e: database::MigrateError) -> Self {
+        Error::Migration(e)
+    }
+}
+
+impl From<database::SeedError> for Error {
+    fn from(e: database::SeedError) -> Self {
+        Error::Seed(e)
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Http(e) => Some(e),
+            Error::Database(e) | Error::Connect(e) => Some(e),
+            Error::Config(e) => Some(e),
+            Error::Migration(e) => Some(e),
+            Error::Seed(e) => Some(e),
+            Error::Bind(e)
+            | Error::Listen(e)
+            | Error::Runtime(e)
+            | Error::Process(e)
+            | Error::Tls(e) => Some(e),
+            Error::Serve(e) => Some(e),
+            Error::TemplateError(e) => Some(e),
+            Error::Panic(_)
+            | Error::Misconfigured(_)
+            | Error::Usage(_)
+            | Error::PageNotFound
+            | Error::BadRequest(_) => None,
+        }
+    }
+}
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match &self {
             Error::Http(e) => write!(f, "{}", e),
             Error::Database(e) => write!(f, "{}", e),
-            Error::FailedToStartServer => write!(f, "Failed to start server"),
+            Error::Config(e) => write!(f, "{}", e),
+            Error::Migration(e) => write!(f, "{}", e),
+            Error::Seed(e) => write!(f, "{}", e),
+            Error::Bind(e) => write!(f, "Failed to bind the listening sockets: {}", e),
+            Error::Listen(e) => write!(f, "Failed to set up the listening sockets: {}", e),
+            Error::Runtime(e) => write!(f, "Failed to start the runtime: {}", e),
+            Error::Connect(e) => write!(f, "Failed to connect to the database: {}", e),
+            Error::Process(e) => write!(f, "Failed to run the workers: {}", e),
+            Error::Serve(e) => write!(f, "Failed to serve: {}", e),
+            Error::Tls(e) => write!(f, "Failed to load the TLS certificate: {}", e),
             Error::PageNotFound => write!(f, "Page not found"),
+            Error::BadRequest(e) => write!(f, "{}", e),
             Error::Panic(e) => write!(f, "{}", e),
+            Error::Misconfigured(e) => write!(f, "{}", e),
+            Error::Usage(e) => write!(f, "{}", e),
             Error::TemplateError(e) => write!(f, "{}", e),
         }
     }
@@ -85,6 +174,13 @@ impl IntoResponse for Error {
                 }
             })
             .into_response(),
+            Error::BadRequest(_) => ErrorTemplate::new(http::StatusCode::BAD_REQUEST, {
+                ErrorDetails {
+                    kind: self,
+                    details: "".to_string(),
+                }
+            })
+            .into_response(),
             _ => ErrorTemplate::new(http::StatusCode::INTERNAL_SERVER_ERROR, {
                 ErrorDetails {
                     kind: self,
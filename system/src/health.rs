@@ -0,0 +1,36 @@
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use serde_json::json;
+
+use crate::{worker_stats, AppState};
+
+/// Liveness: the process answers requests. Reports the pool without
+/// touching the database, so a database outage does not get workers killed,
+/// and the [`worker_stats`] of the prefork master, if any.
+pub async fn healthz(State(state): State<AppState>) -> impl IntoResponse {
+    Json(json!({
+        "status": "ok",
+        "pool": state.db.pool_status(),
+        "workers": worker_stats(),
+    }))
+}
+
+/// Readiness: the primary database answers, `503` otherwise so the
+/// orchestrator stops routing traffic here until it recovers. Replicas are
+/// checked and reported too, but reads fall back to the primary when they
+/// are all down, so they do not decide readiness.
+pub async fn readyz(State(state): State<AppState>) -> impl IntoResponse {
+    let health = state.db.health().await;
+    let status = if health.reachable {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (
+        status,
+        Json(json!({
+            "status": if health.reachable { "ok" } else { "unavailable" },
+            "database": health,
+        })),
+    )
+}
@@ -1,4 +1,15 @@
 mod error;
+mod health;
+mod listener;
+mod log;
+mod pagination;
+mod process;
+mod query_stats;
+mod read_only;
+mod shutdown;
+mod sse;
+mod tls;
+mod transaction;
 mod utils;
 
 #[cfg(not(debug_assertions))]
@@ -7,32 +18,59 @@ use serde_json::Value;
 use std::cell::OnceCell;
 
 use std::{
-    net::{SocketAddr, TcpListener},
-    sync::Arc,
+    any::{Any, TypeId},
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+    time::Duration,
 };
 
 use askama::Template;
 pub use axum::*;
 use axum::{
+    extract::DefaultBodyLimit,
     handler::HandlerWithoutStateExt,
     response::{Html, IntoResponse, Response as AxumResponse},
     routing::get,
 };
-use config::Config;
-use database::DB;
-use prefork::{Prefork, DEFAULT_NUM_PROCESSES};
-use tokio::runtime::Builder;
-use tower_http::services::ServeDir;
+use config::{Check, Config, Loader, Section, Watcher};
+use database::{Migrator, DB};
+use futures_util::future::BoxFuture;
+use hyper::server::accept::Accept;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    runtime::Builder,
+    sync::watch,
+};
+use tower_http::{services::ServeDir, timeout::TimeoutLayer};
 
 pub use crate::error::{panic_handler, Error};
+pub use crate::health::{healthz, readyz};
+pub use crate::pagination::Pagination;
+pub use crate::process::{worker_stats, WorkerStats};
+pub use crate::read_only::read_only_layer;
+pub use crate::shutdown::shutting_down;
+pub use crate::sse::sse;
+pub use crate::transaction::{transaction_layer, Tx};
 pub use crate::utils::*;
 
+use crate::listener::{Listeners, Role, Socket, UnixIncoming};
+use crate::process::{Master, Ready};
+use crate::query_stats::{query_stats_layer, QueryReport};
+use crate::shutdown::Shutdown;
+use crate::tls::{Certificates, TlsIncoming};
+
 pub type Result<T> = std::result::Result<T, Error>;
 
 pub struct State {
     pub db: DB,
+    config: watch::Receiver<Arc<Config>>,
+    sections: RwLock<SectionCache>,
 }
 
+/// Deserialized sections along with the config they were read from.
+type SectionCache = HashMap<TypeId, (Arc<Config>, Arc<dyn Any + Send + Sync>)>;
+
 #[cfg(debug_assertions)]
 pub const PRODUCTION: bool = false;
 
@@ -55,16 +93,73 @@ pub type Router = axum::Router<AppState>;
 
 pub type Response<T> = Result<T>;
 
+/// The server. Settings left unset on the builder fall back to the `server`
+/// section of the loaded [`Config`].
 pub struct System {
-    address: SocketAddr,
-    prefork: u32,
+    address: Option<SocketAddr>,
+    prefork: Option<u32>,
     router: Router,
-    config_path: String,
-    config: Config,
+    /// Routers of the `server.listeners`, by name.
+    routers: Vec<(String, Router)>,
+    loader: Loader,
+    config: Option<Config>,
+    loaded: Option<Config>,
     db: Option<DB>,
+    sections: Vec<Check>,
+    hot_reload: bool,
+    migrate: bool,
+    health_checks: bool,
+    /// Loaded before forking, so a bad certificate fails the launch.
+    certificates: Option<Arc<Certificates>>,
 }
 
 impl State {
+    pub fn new(db: DB, config: Config) -> Self {
+        Self::with_receiver(db, watch::channel(Arc::new(config)).1)
+    }
+
+    /// Creates a state whose config follows `config`, e.g. the receiver
+    /// returned by [`Watcher::spawn`].
+    pub fn with_receiver(db: DB, config: watch::Receiver<Arc<Config>>) -> Self {
+        Self {
+            db,
+            config,
+            sections: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// The current config, which changes on reload when hot reloading is on.
+    pub fn settings(&self) -> Arc<Config> {
+        self.config.borrow().clone()
+    }
+
+    /// A receiver notified whenever the config is reloaded.
+    pub fn subscribe(&self) -> watch::Receiver<Arc<Config>> {
+        self.config.clone()
+    }
+
+    /// The application config section `T`, deserialized on first use and
+    /// cached until the config is reloaded.
+    pub fn config<T: Section>(&self) -> Result<Arc<T>> {
+        let id = TypeId::of::<T>();
+        let config = self.settings();
+        if let Some((source, section)) = self.sections.read().unwrap().get(&id) {
+            if Arc::ptr_eq(source, &config) {
+                return Ok(section
+                    .clone()
+                    .downcast::<T>()
+                    .expect("sections are keyed by their type"));
+            }
+        }
+
+        let section = Arc::new(config.get::<T>()?);
+        self.sections
+            .write()
+            .unwrap()
+            .insert(id, (config, section.clone()));
+        Ok(section)
+    }
+
     pub fn render<T>(&self, template: T) -> AxumResponse
     where
         T: Template,
@@ -79,12 +174,19 @@ impl State {
 impl Default for System {
     fn default() -> Self {
         Self {
-            prefork: 1,
-            address: "0.0.0.0:3000".parse().unwrap(),
+            prefork: None,
+            address: None,
             router: Router::new().route("/", get(|| async { "Hello, World!" })),
-            config_path: "".to_string(),
-            config: Config::default(),
+            routers: Vec::new(),
+            loader: Loader::default(),
+            config: None,
+            loaded: None,
             db: None,
+            sections: Vec::new(),
+            hot_reload: false,
+            migrate: false,
+            health_checks: true,
+            certificates: None,
         }
     }
 }
@@ -103,7 +205,7 @@ impl System {
 
     pub fn with_config(config: Config) -> Self {
         System {
-            config,
+            config: Some(config),
             ..Default::default()
         }
     }
@@ -121,17 +223,24 @@ impl System {
     }
 
     pub fn config_path(mut self, config_path: &str) -> Self {
-        self.config_path = config_path.to_string();
+        self.loader.set_file(config_path);
         self
     }
 
+    /// Uses `config` as is, skipping the files and environment variables
+    /// the loader would otherwise read.
     pub fn config(mut self, config: Config) -> Self {
-        self.config = config;
+        self.config = Some(config);
+        self
+    }
+
+    pub fn loader(mut self, loader: Loader) -> Self {
+        self.loader = loader;
         self
     }
 
     pub fn prefork(mut self, num_process: u32) -> Self {
-        self.prefork = num_process;
+        self.prefork = Some(num_process);
         self
     }
 
@@ -141,7 +250,7 @@ impl System {
     }
 
     pub fn address(mut self, address: SocketAddr) -> Self {
-        self.address = address;
+        self.address = Some(address);
         self
     }
 
@@ -150,18 +259,38 @@ impl System {
         self
     }
 
+    /// Serves `router` on the addresses of `server.listeners.<name>`, e.g.
+    /// an admin or metrics port kept off the public one. It shares the
+    /// state and middleware of the application, but not its health checks
+    /// and static files.
+    pub fn listener(mut self, name: &str, router: Router) -> Self {
+        self.set_listener(name, router);
+        self
+    }
+
+    pub fn set_listener(&mut self, name: &str, router: Router) -> &mut Self {
+        self.routers.retain(|(existing, _)| existing != name);
+        self.routers.push((name.to_string(), router));
+        self
+    }
+
     pub fn set_config(&mut self, config: Config) -> &mut Self {
-        self.config = config;
+        self.config = Some(config);
         self
     }
 
     pub fn set_config_path(&mut self, config_path: &str) -> &mut Self {
-        self.config_path = config_path.to_string();
+        self.loader.set_file(config_path);
+        self
+    }
+
+    pub fn set_loader(&mut self, loader: Loader) -> &mut Self {
+        self.loader = loader;
         self
     }
 
     pub fn set_prefork(&mut self, num_process: u32) -> &mut Self {
-        self.prefork = num_process;
+        self.prefork = Some(num_process);
         self
     }
 
@@ -170,98 +299,408 @@ impl System {
         self
     }
 
-    async fn create_state(&self) -> AppState {
-        let mut config = self.config.clone();
+    /// Checks at startup that the application section `T` deserializes, so
+    /// a bad value fails the launch instead of the first request using it.
+    pub fn section<T: Section>(mut self) -> Self {
+        self.sections.push(check_section::<T>);
+        self
+    }
+
+    pub fn set_section<T: Section>(&mut self) -> &mut Self {
+        self.sections.push(check_section::<T>);
+        self
+    }
 
-        if !self.config_path.is_empty() {
-            config = Config::load_config_file(&self.config_path);
+    /// Reloads the config when its files change or on `SIGHUP`. Only the
+    /// log level and application sections apply live, other changes are
+    /// reported as needing a restart. Has no effect with [`System::config`].
+    pub fn hot_reload(mut self, enabled: bool) -> Self {
+        self.hot_reload = enabled;
+        self
+    }
+
+    pub fn set_hot_reload(&mut self, enabled: bool) -> &mut Self {
+        self.hot_reload = enabled;
+        self
+    }
+
+    /// Applies pending migrations from `database.migrations` on startup.
+    /// Every prefork worker tries, but an advisory lock lets only the first
+    /// one run them while the others wait.
+    pub fn migrate(mut self, enabled: bool) -> Self {
+        self.migrate = enabled;
+        self
+    }
+
+    pub fn set_migrate(&mut self, enabled: bool) -> &mut Self {
+        self.migrate = enabled;
+        self
+    }
+
+    /// Serves [`healthz`] at `/healthz` and [`readyz`] at `/readyz`, on by
+    /// default. They skip the body limit, timeout and transaction layers,
+    /// and a route of the application with the same path takes precedence.
+    pub fn health_checks(mut self, enabled: bool) -> Self {
+        self.health_checks = enabled;
+        self
+    }
+
+    pub fn set_health_checks(&mut self, enabled: bool) -> &mut Self {
+        self.health_checks = enabled;
+        self
+    }
+
+    /// The config the server runs with: the one given to [`System::config`],
+    /// or else the result of the loader.
+    pub fn load_config(&self) -> Result<Config> {
+        match &self.config {
+            Some(config) => Ok(config.clone()),
+            None => Ok(self.loader.load()?),
         }
+    }
 
+    /// Loads the config and runs every check on it: [`Config::validate`]
+    /// and the sections registered with [`System::section`].
+    pub fn check_config(&self) -> Result<Config> {
+        let config = self.load_config()?;
+        config.validate()?;
+        for check in &self.sections {
+            check(&config)?;
+        }
+        if let Some(name) = config
+            .server
+            .listeners
+            .keys()
+            .find(|name| !self.routers.iter().any(|(router, _)| router == *name))
+        {
+            return Err(unknown_listener(name).into());
+        }
+        Ok(config)
+    }
+
+    /// Loads the config and only checks its `database` section, with
+    /// [`Config::validate_database`], for commands that do not serve
+    /// requests.
+    pub fn check_database_config(&self) -> Result<Config> {
+        let config = self.load_config()?;
+        config.validate_database()?;
+        Ok(config)
+    }
+
+    fn watch_config(&self, config: Config) -> Result<watch::Receiver<Arc<Config>>> {
+        if !self.hot_reload || self.config.is_some() {
+            return Ok(watch::channel(Arc::new(config)).1);
+        }
+
+        let watcher = self
+            .sections
+            .iter()
+            .fold(Watcher::new(self.loader.clone()), |watcher, check| {
+                watcher.check(*check)
+            });
+        let receiver = watcher.spawn(config)?;
+
+        let mut changes = receiver.clone();
+        tokio::spawn(async move {
+            let mut level = changes.borrow().log.level.clone();
+            while changes.changed().await.is_ok() {
+                let current = changes.borrow().log.level.clone();
+                if current != level {
+                    log::set_level(&current);
+                    level = current;
+                }
+            }
+        });
+
+        Ok(receiver)
+    }
+
+    async fn create_state(&self, config: &Config) -> Result<AppState> {
+        // Watching takes over `SIGHUP` before the possibly long wait for the
+        // database, as the signal would end the process until then.
+        let config_receiver = self.watch_config(config.clone())?;
         let db = match self.db.clone() {
             Some(db) => db,
-            None => match DB::connect(&config.database.to_database_url()).await {
-                Ok(db) => db,
-                Err(e) => {
-                    panic!("Failed to connect to database: {}", e);
-                }
-            },
+            None => DB::connect_with_retry(&config.database)
+                .await
+                .map_err(Error::Connect)?,
         };
 
-        Arc::new(State { db })
+        if self.migrate {
+            Migrator::from_dir(&config.database.migrations)?
+                .up(&db)
+                .await?;
+        }
+
+        Ok(Arc::new(State::with_receiver(db, config_receiver)))
     }
 
-    async fn server(&self, listener: TcpListener) -> Result<()> {
+    /// Wraps `router` in the middleware every listener shares.
+    fn layers(&self, router: Router, config: &Config) -> Router {
+        let server = &config.server;
+        let mut router = router
+            .layer(middleware::from_fn(transaction_layer))
+            .layer(middleware::from_fn_with_state(
+                QueryReport {
+                    // Repeated queries are expected to be fixed before
+                    // going to production, not reported there.
+                    n_plus_one_threshold: if config.app.is_production() {
+                        0
+                    } else {
+                        config.database.n_plus_one_threshold
+                    },
+                },
+                query_stats_layer,
+            ))
+            .layer(DefaultBodyLimit::max(server.body_limit));
+        if server.request_timeout > 0 {
+            router = router.layer(TimeoutLayer::new(Duration::from_secs(
+                server.request_timeout,
+            )));
+        }
+        router
+    }
+
+    async fn server(&self, listeners: Listeners, ready: Ready) -> Result<()> {
         async fn not_found() -> Error {
             Error::PageNotFound
         }
 
-        let public_dir = ServeDir::new("public").not_found_service(not_found.into_service());
+        let config = match &self.loaded {
+            Some(config) => config.clone(),
+            None => self.load_config()?,
+        };
+        let server = &config.server;
 
-        Server::from_tcp(listener)
-            .map_err(|_| Error::FailedToStartServer)?
-            .serve(
-                self.router
-                    .clone()
-                    .with_state(self.create_state().await)
-                    .fallback_service(public_dir)
-                    .into_make_service(),
-            )
-            .await
-            .map_err(|_| Error::FailedToStartServer)?;
+        let public_dir =
+            ServeDir::new(&server.static_dir).not_found_service(not_found.into_service());
+
+        let router = self.layers(self.router.clone(), &config);
+
+        // Without a master, this process hands the sockets over on SIGUSR2.
+        let upgrade = match ready.upgrades() {
+            true => Some(listeners.try_clone().map_err(Error::Listen)?),
+            false => None,
+        };
+        let shutdown = Shutdown::new(upgrade).map_err(Error::Runtime)?;
+
+        let state = self.create_state(&config).await?;
+        // The probes are only reached when no route of the application
+        // matches, as adding a route twice panics.
+        let app = router.with_state(state.clone());
+        let app = match self.health_checks {
+            true => app.fallback_service(
+                Router::new()
+                    .route("/healthz", get(healthz))
+                    .route("/readyz", get(readyz))
+                    .with_state(state.clone())
+                    .fallback_service(public_dir),
+            ),
+            false => app.fallback_service(public_dir),
+        };
+        let acceptor = match (&server.tls, &self.certificates) {
+            (Some(tls), Some(certificates)) => {
+                tokio::spawn(certificates.clone().watch());
+                Some(tls::acceptor(certificates.clone(), tls.http2))
+            }
+            _ => None,
+        };
+
+        let mut servers: Vec<BoxFuture<'_, hyper::Result<()>>> = Vec::new();
+        // Plain HTTP is sent to the first TCP socket serving the application.
+        let https_port = listeners
+            .0
+            .iter()
+            .find_map(|(role, _, socket)| match (role, socket) {
+                (Role::App, Socket::Tcp(listener)) => listener.local_addr().ok().map(|a| a.port()),
+                _ => None,
+            });
+        for (role, _, socket) in listeners.0 {
+            let router = match &role {
+                Role::App => app.clone(),
+                Role::Redirect => {
+                    tls::redirect(https_port.ok_or_else(|| config::Error::Invalid {
+                        key: Some("server.tls.redirect_port".to_string()),
+                        message:
+                            "needs the application to listen on TCP to redirect to".to_string(),
+                    })?)
+                }
+                Role::Router(name) => {
+                    let (_, router) = self
+                        .routers
+                        .iter()
+                        .find(|(router, _)| router == name)
+                        .ok_or_else(|| unknown_listener(name))?;
+                    self.layers(router.clone(), &config)
+                        .with_state(state.clone())
+                }
+            };
+            servers.push(match (socket, &acceptor) {
+                (Socket::Tcp(listener), Some(acceptor)) if role == Role::App => {
+                    let handshake_timeout = match server.header_read_timeout {
+                        0 => Duration::from_secs(10),
+                        timeout => Duration::from_secs(timeout),
+                    };
+                    let incoming = TlsIncoming::new(listener, acceptor.clone(), handshake_timeout)
+                        .map_err(Error::Listen)?;
+                    // Leaving `h2` out of ALPN does not stop a client from
+                    // starting HTTP/2 with prior knowledge.
+                    let http1_only = server.tls.as_ref().is_some_and(|tls| !tls.http2);
+                    serve(
+                        Server::builder(incoming).http1_only(http1_only),
+                        router,
+                        server,
+                    )
+                }
+                (Socket::Tcp(listener), _) => serve(
+                    Server::from_tcp(listener).map_err(Error::Serve)?,
+                    router,
+                    server,
+                ),
+                (Socket::Unix(listener), _) => serve(
+                    Server::builder(UnixIncoming::new(listener).map_err(Error::Listen)?),
+                    router,
+                    server,
+                ),
+            });
+        }
+        let serving = futures_util::future::try_join_all(servers);
+        ready.notify();
+
+        tokio::pin!(serving);
+        tokio::select! {
+            result = &mut serving => return result.map(|_| ()).map_err(Error::Serve),
+            _ = shutdown.wait() => {}
+        }
+
+        // New connections are refused from here, while the requests in
+        // flight get until the drain timeout to finish.
+        let drain = Duration::from_secs(server.shutdown_timeout);
+        tracing::info!(
+            "Shutting down, draining requests for up to {}s",
+            drain.as_secs()
+        );
+        if tokio::time::timeout(drain, serving).await.is_err() {
+            tracing::warn!("Requests still running after the drain timeout, closing them");
+        }
 
         Ok(())
     }
 
-    pub fn run(self) -> Result<()> {
-        let listener = TcpListener::bind(self.address).expect("Failed to bind to address");
-        if self.prefork == 1 {
-            Builder::new_multi_thread()
+    /// Serves until `SIGTERM` or `SIGINT`, then drains the requests in flight
+    /// for up to `server.shutdown_timeout`. With several workers, a master
+    /// process forks them and forwards the signals.
+    ///
+    /// Listens on `server.host` and `server.port`, or the `server.listen`
+    /// addresses, which may be Unix sockets or sockets passed by systemd.
+    /// The routers of [`System::listener`] get their own addresses. With
+    /// `server.tls`, the application serves HTTPS and HTTP/2 on TCP, and
+    /// plain HTTP on `server.tls.redirect_port` is redirected there.
+    ///
+    /// `SIGUSR2` reloads without dropping requests: the executable is run
+    /// again with the listening sockets, and once the new generation serves
+    /// it stops this one, which drains. Deploys replace the executable file
+    /// and then signal the running server. Under a process manager, the
+    /// new generation has a different process id than the one it tracks.
+    pub fn run(mut self) -> Result<()> {
+        let config = self.check_config()?;
+        log::init(&config.log);
+        process::remember_executable();
+
+        let listeners = Listeners::bind(&config.server, self.address).map_err(Error::Bind)?;
+        for (role, _, socket) in &listeners.0 {
+            match role {
+                Role::App => tracing::info!("Listening on {}", socket),
+                Role::Redirect => tracing::info!("Redirecting to HTTPS on {}", socket),
+                Role::Router(name) => tracing::info!("Listening for `{}` on {}", name, socket),
+            }
+        }
+        if let Some(tls) = &config.server.tls {
+            self.certificates = Some(Certificates::load(tls).map_err(Error::Tls)?);
+        }
+        let replaces = process::replaced_pid();
+        let workers = match self.prefork.unwrap_or(config.server.workers) {
+            0 => std::thread::available_parallelism().map_or(1, |n| n.get() as u32),
+            workers => workers,
+        };
+        let master = Master {
+            workers,
+            shutdown_timeout: Duration::from_secs(config.server.shutdown_timeout),
+            reload: self.hot_reload && self.config.is_none(),
+            replaces,
+        };
+
+        // Forked workers reuse this config instead of loading it again.
+        self.loaded = Some(config);
+
+        if workers == 1 {
+            return Builder::new_multi_thread()
                 .enable_all()
                 .build()
-                .expect("cannot create runtime")
-                .block_on(async {
-                    match self.server(listener).await {
-                        Ok(_) => {}
-                        Err(e) => {
-                            eprintln!("Failed to start server: {}", e);
-                            return;
-                        }
-                    }
-                })
-        } else {
-            let num_processes = if self.prefork != 0 {
-                self.prefork
-            } else {
-                DEFAULT_NUM_PROCESSES
-            };
-            if Prefork::from_resource((listener, self))
-                .with_num_processes(num_processes)
-                .with_init(|child_num, (listener, app)| {
-                    Builder::new_multi_thread()
-                        .enable_all()
-                        .build()
-                        .expect("cannot create runtime")
-                        .block_on(async {
+                .map_err(Error::Runtime)?
+                .block_on(self.server(listeners, Ready::Process(replaces)));
+        }
+
+        // A worker failing to start is restarted by the master, so its error
+        // is only logged.
+        master
+            .run(&listeners, |worker, ready| {
+                let served = Builder::new_multi_thread()
+                    .enable_all()
+                    .build()
+                    .map_err(Error::Runtime)
+                    .and_then(|runtime| {
+                        runtime.block_on(async {
                             let pid = std::process::id();
-                            println!("Child {} (PID {}) started", child_num, pid);
-                            match app.server(listener).await {
-                                Ok(_) => {}
-                                Err(e) => {
-                                    eprintln!("Failed to start server: {}", e);
-                                    return;
-                                }
-                            }
+                            tracing::info!("Worker {} (PID {}) started", worker, pid);
+                            self.server(listeners.try_clone().map_err(Error::Listen)?, ready)
+                                .await
                         })
-                })
-                .fork()
-                .map_err(|_| Error::FailedToStartServer)?
-            {
-                println!("Parent is exiting");
-            }
-        }
+                    });
+                match served {
+                    Ok(()) => 0,
+                    Err(e) => {
+                        tracing::error!("Worker {} failed: {}", worker, e);
+                        1
+                    }
+                }
+            })
+            .map_err(Error::Process)
+    }
+}
 
-        Ok(())
+/// Serves `router` on the connections of `builder` until the server shuts
+/// down, with the `server` settings of HTTP connections.
+fn serve<'a, I>(
+    mut builder: hyper::server::Builder<I>,
+    router: axum::Router,
+    server: &config::Server,
+) -> BoxFuture<'a, hyper::Result<()>>
+where
+    I: Accept + Send + 'a,
+    I::Conn: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    I::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    if server.header_read_timeout > 0 {
+        builder =
+            builder.http1_header_read_timeout(Duration::from_secs(server.header_read_timeout));
     }
+    Box::pin(
+        builder
+            .serve(router.into_make_service())
+            .with_graceful_shutdown(shutting_down()),
+    )
+}
+
+fn unknown_listener(name: &str) -> config::Error {
+    config::Error::Invalid {
+        key: Some(format!("server.listeners.{}", name)),
+        message: "no router is registered under this name with `System::listener`".to_string(),
+    }
+}
+
+fn check_section<T: Section>(config: &Config) -> std::result::Result<(), config::Error> {
+    config.get::<T>().map(|_| ())
 }
 
 pub fn asset(path: &str) -> String {
@@ -0,0 +1,420 @@
+use std::{
+    fmt, io,
+    net::{SocketAddr, TcpListener, ToSocketAddrs},
+    os::{
+        fd::{AsRawFd, FromRawFd, RawFd},
+        unix::{
+            fs::FileTypeExt,
+            net::{UnixListener, UnixStream},
+        },
+    },
+    pin::Pin,
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+
+use config::Address;
+use futures_util::Future;
+use hyper::server::accept::Accept;
+
+use crate::process;
+
+/// What a socket serves.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub(crate) enum Role {
+    App,
+    /// Plain HTTP redirected to HTTPS, see `server.tls.redirect_port`.
+    Redirect,
+    /// The router registered under this name with [`crate::System::listener`].
+    Router(String),
+}
+
+pub(crate) enum Socket {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl Socket {
+    fn try_clone(&self) -> io::Result<Self> {
+        Ok(match self {
+            Socket::Tcp(listener) => Socket::Tcp(listener.try_clone()?),
+            Socket::Unix(listener) => Socket::Unix(listener.try_clone()?),
+        })
+    }
+
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Socket::Tcp(listener) => listener.as_raw_fd(),
+            Socket::Unix(listener) => listener.as_raw_fd(),
+        }
+    }
+
+    /// Takes ownership of a listening socket passed by another process,
+    /// telling TCP and Unix sockets apart by their address family.
+    ///
+    /// # Safety
+    ///
+    /// `fd` must be an open listening socket that nothing else owns.
+    unsafe fn from_raw_fd(fd: RawFd) -> io::Result<Self> {
+        // So that processes started from now on do not inherit it too.
+        libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+
+        let mut address: libc::sockaddr_storage = std::mem::zeroed();
+        let mut len = std::mem::size_of_val(&address) as libc::socklen_t;
+        if libc::getsockname(
+            fd,
+            (&mut address as *mut libc::sockaddr_storage).cast(),
+            &mut len,
+        ) == -1
+        {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(match address.ss_family as libc::c_int {
+            libc::AF_UNIX => Socket::Unix(UnixListener::from_raw_fd(fd)),
+            _ => Socket::Tcp(TcpListener::from_raw_fd(fd)),
+        })
+    }
+
+    /// Whether the socket is bound to `address`, for TCP and Unix sockets.
+    fn listens_on(&self, address: &Address) -> bool {
+        match (self, address) {
+            (Socket::Tcp(listener), Address::Tcp(address)) => {
+                match (listener.local_addr(), address.to_socket_addrs()) {
+                    (Ok(local), Ok(mut resolved)) => resolved.any(|address| address == local),
+                    _ => false,
+                }
+            }
+            (Socket::Unix(listener), Address::Unix(path)) => listener
+                .local_addr()
+                .is_ok_and(|local| local.as_pathname() == Some(path.as_path())),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Socket {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Socket::Tcp(listener) => match listener.local_addr() {
+                Ok(address) => write!(f, "{}", address),
+                Err(_) => write!(f, "a TCP socket"),
+            },
+            Socket::Unix(listener) => match listener.local_addr() {
+                Ok(address) => match address.as_pathname() {
+                    Some(path) => write!(f, "unix:{}", path.display()),
+                    None => write!(f, "an unnamed Unix socket"),
+                },
+                Err(_) => write!(f, "a Unix socket"),
+            },
+        }
+    }
+}
+
+/// The sockets the server accepts connections on, bound before forking so
+/// that every worker shares them, with the address each was bound for.
+pub(crate) struct Listeners(pub(crate) Vec<(Role, Address, Socket)>);
+
+impl Listeners {
+    /// Binds the sockets `server` asks for, or takes them over from the
+    /// previous generation or from systemd. `address` overrides the
+    /// addresses of the application.
+    pub(crate) fn bind(server: &config::Server, address: Option<SocketAddr>) -> io::Result<Self> {
+        let mut addresses = Vec::new();
+        match address {
+            Some(address) => addresses.push((Role::App, Address::Tcp(address.to_string()))),
+            None if server.listen.is_empty() => {
+                addresses.push((Role::App, Address::tcp(&server.host, server.port)))
+            }
+            None => addresses.extend(
+                server
+                    .listen
+                    .iter()
+                    .map(|address| (Role::App, address.clone())),
+            ),
+        }
+        if let Some(port) = server.tls.as_ref().and_then(|tls| tls.redirect_port) {
+            addresses.push((Role::Redirect, Address::tcp(&server.host, port)));
+        }
+        for (name, listener) in &server.listeners {
+            addresses.extend(
+                listener
+                    .listen
+                    .iter()
+                    .map(|address| (Role::Router(name.clone()), address.clone())),
+            );
+        }
+
+        // A new generation takes over the sockets of the previous one, and
+        // binds those of the addresses added since. The sockets of removed
+        // addresses are closed, leaving them to the previous generation.
+        let mut inherited = process::inherited_fds()
+            .into_iter()
+            // SAFETY: the previous generation passed its sockets for this
+            // purpose.
+            .map(|(fd, address)| Ok((address, unsafe { Socket::from_raw_fd(fd)? })))
+            .collect::<io::Result<Vec<_>>>()?;
+        let mut activated = Activated::take();
+        let mut sockets = Vec::new();
+        for (role, address) in addresses {
+            let socket = match take_inherited(&mut inherited, &address) {
+                Some(socket) => socket,
+                None => bind(&address, &mut activated)
+                    .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", address, e)))?,
+            };
+            sockets.push((role, address, socket));
+        }
+
+        Ok(Self(sockets))
+    }
+
+    pub(crate) fn try_clone(&self) -> io::Result<Self> {
+        self.0
+            .iter()
+            .map(|(role, address, socket)| Ok((role.clone(), address.clone(), socket.try_clone()?)))
+            .collect::<io::Result<_>>()
+            .map(Self)
+    }
+
+    /// The descriptors to hand over, with the address [`Listeners::bind`]
+    /// takes each back for.
+    pub(crate) fn fds(&self) -> Vec<(RawFd, String)> {
+        self.0
+            .iter()
+            .map(|(_, address, socket)| (socket.as_raw_fd(), address.to_string()))
+            .collect()
+    }
+}
+
+/// Takes the inherited socket bound for `address`, or else one bound to
+/// the same address, as when `localhost:3000` became `127.0.0.1:3000`.
+fn take_inherited(inherited: &mut Vec<(String, Socket)>, address: &Address) -> Option<Socket> {
+    let index = inherited
+        .iter()
+        .position(|(bound_for, _)| *bound_for == address.to_string())
+        .or_else(|| {
+            inherited
+                .iter()
+                .position(|(_, socket)| socket.listens_on(address))
+        })?;
+    Some(inherited.remove(index).1)
+}
+
+fn bind(address: &Address, activated: &mut Activated) -> io::Result<Socket> {
+    match address {
+        Address::Tcp(address) => Ok(Socket::Tcp(TcpListener::bind(address.as_str())?)),
+        Address::Unix(path) => {
+            // Left behind by a previous run, binding fails otherwise. One a
+            // server still accepts connections on is left alone.
+            if path
+                .metadata()
+                .is_ok_and(|meta| meta.file_type().is_socket())
+            {
+                if UnixStream::connect(path).is_ok() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AddrInUse,
+                        "another server is listening on it",
+                    ));
+                }
+                std::fs::remove_file(path)?;
+            }
+            Ok(Socket::Unix(UnixListener::bind(path)?))
+        }
+        Address::Systemd(name) => {
+            let fd = activated.claim(name.as_deref()).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, "systemd passed no such socket")
+            })?;
+            // SAFETY: systemd passed this socket to this process, and it is
+            // only claimed once.
+            unsafe { Socket::from_raw_fd(fd) }
+        }
+    }
+}
+
+/// The sockets passed by systemd socket activation, see `sd_listen_fds(3)`.
+struct Activated(Vec<(String, RawFd)>);
+
+impl Activated {
+    /// The first descriptor systemd passes.
+    const FIRST_FD: RawFd = 3;
+
+    /// Takes the sockets from the environment, which is cleared so that
+    /// processes started later do not take them too.
+    fn take() -> Self {
+        let for_this_process = std::env::var("LISTEN_PID")
+            .ok()
+            .and_then(|pid| pid.parse::<u32>().ok())
+            == Some(std::process::id());
+        let count = std::env::var("LISTEN_FDS")
+            .ok()
+            .and_then(|count| count.parse::<RawFd>().ok())
+            .filter(|_| for_this_process)
+            .unwrap_or(0);
+        let names = std::env::var("LISTEN_FDNAMES").unwrap_or_default();
+        for var in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+            std::env::remove_var(var);
+        }
+
+        let mut names = names.split(':');
+        Self(
+            (Self::FIRST_FD..Self::FIRST_FD + count)
+                .map(|fd| (names.next().unwrap_or("unknown").to_string(), fd))
+                .collect(),
+        )
+    }
+
+    /// The first unclaimed socket, named `name` if given.
+    fn claim(&mut self, name: Option<&str>) -> Option<RawFd> {
+        let index = self
+            .0
+            .iter()
+            .position(|(fd_name, _)| name.is_none_or(|name| fd_name == name))?;
+        Some(self.0.remove(index).1)
+    }
+}
+
+/// The connections of a Unix socket.
+pub(crate) struct UnixIncoming {
+    listener: tokio::net::UnixListener,
+    /// Set after failing to accept, e.g. when out of file descriptors.
+    backoff: Option<Pin<Box<tokio::time::Sleep>>>,
+}
+
+impl UnixIncoming {
+    pub(crate) fn new(listener: UnixListener) -> io::Result<Self> {
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener: tokio::net::UnixListener::from_std(listener)?,
+            backoff: None,
+        })
+    }
+}
+
+impl Accept for UnixIncoming {
+    type Conn = tokio::net::UnixStream;
+    type Error = io::Error;
+
+    fn poll_accept(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        if let Some(backoff) = &mut self.backoff {
+            ready!(backoff.as_mut().poll(cx));
+            self.backoff = None;
+        }
+
+        loop {
+            match ready!(self.listener.poll_accept(cx)) {
+                Ok((stream, _)) => return Poll::Ready(Some(Ok(stream))),
+                // Errors of the connection only, not of the listener.
+                Err(e) if e.kind() == io::ErrorKind::ConnectionAborted => continue,
+                Err(e) => {
+                    // Failing would stop the server, so wait for things to
+                    // improve like hyper does for TCP.
+                    tracing::warn!("Failed to accept a connection: {}", e);
+                    let mut backoff = Box::pin(tokio::time::sleep(Duration::from_secs(1)));
+                    if backoff.as_mut().poll(cx).is_pending() {
+                        self.backoff = Some(backoff);
+                        return Poll::Pending;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn tcp() -> (Socket, u16) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        (Socket::Tcp(listener), port)
+    }
+
+    fn unix(name: &str) -> (Socket, PathBuf) {
+        let path = std::env::temp_dir().join(format!("jaya-{}-{}.sock", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        (Socket::Unix(UnixListener::bind(&path).unwrap()), path)
+    }
+
+    fn port_of(socket: &Socket) -> u16 {
+        match socket {
+            Socket::Tcp(listener) => listener.local_addr().unwrap().port(),
+            Socket::Unix(_) => panic!("not a TCP socket"),
+        }
+    }
+
+    #[test]
+    fn takes_the_socket_bound_for_the_address() {
+        let (first, first_port) = tcp();
+        let (second, second_port) = tcp();
+        let address = Address::Tcp(format!("127.0.0.1:{}", second_port));
+        let mut inherited = vec![
+            (format!("127.0.0.1:{}", first_port), first),
+            (address.to_string(), second),
+        ];
+
+        let socket = take_inherited(&mut inherited, &address).unwrap();
+        assert_eq!(port_of(&socket), second_port);
+        assert_eq!(inherited.len(), 1);
+        assert!(take_inherited(&mut inherited, &address).is_none());
+    }
+
+    #[test]
+    fn takes_a_socket_bound_to_the_same_address_under_another_name() {
+        let (socket, port) = tcp();
+        let mut inherited = vec![(format!("localhost:{}", port), socket)];
+
+        let address = Address::Tcp(format!("127.0.0.1:{}", port));
+        let socket = take_inherited(&mut inherited, &address).unwrap();
+        assert_eq!(port_of(&socket), port);
+        assert!(inherited.is_empty());
+    }
+
+    #[test]
+    fn matches_unix_sockets_by_path() {
+        let (socket, path) = unix("inherited");
+        let (other, other_path) = unix("other");
+        let mut inherited = vec![
+            ("unix:old.sock".to_string(), other),
+            ("unix:renamed.sock".to_string(), socket),
+        ];
+
+        assert!(take_inherited(&mut inherited, &Address::Unix("missing.sock".into())).is_none());
+        assert!(take_inherited(&mut inherited, &Address::Unix(path.clone())).is_some());
+        assert_eq!(inherited.len(), 1);
+        assert!(!inherited[0].1.listens_on(&Address::Unix(path.clone())));
+
+        for path in [path, other_path] {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    #[test]
+    fn leaves_sockets_of_other_kinds() {
+        let (socket, port) = tcp();
+        let mut inherited = vec![(format!("127.0.0.1:{}", port), socket)];
+
+        assert!(take_inherited(&mut inherited, &Address::Systemd(None)).is_none());
+        assert!(take_inherited(&mut inherited, &Address::Unix("jaya.sock".into())).is_none());
+        assert_eq!(inherited.len(), 1);
+    }
+
+    #[test]
+    fn claims_systemd_sockets_by_name_or_in_order() {
+        let mut activated = Activated(vec![
+            ("web".to_string(), 3),
+            ("admin".to_string(), 4),
+            ("unknown".to_string(), 5),
+        ]);
+
+        assert_eq!(activated.claim(Some("admin")), Some(4));
+        assert_eq!(activated.claim(Some("admin")), None);
+        assert_eq!(activated.claim(None), Some(3));
+        assert_eq!(activated.claim(None), Some(5));
+        assert_eq!(activated.claim(None), None);
+    }
+}
@@ -0,0 +1,47 @@
+use std::sync::OnceLock;
+
+use config::{Log, LogFormat};
+use database::{QueryLayer, QUERY_TARGET};
+use tracing::Level;
+use tracing_subscriber::{filter::Targets, prelude::*, reload, EnvFilter, Registry};
+
+static FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+/// Installs the global subscriber from the `log` section. Does nothing if
+/// the application already installed its own.
+///
+/// The level only filters what is printed: statements are still seen by
+/// the [`QueryLayer`] that counts them per request.
+pub fn init(log: &Log) {
+    let (filter, handle) = reload::Layer::new(filter(&log.level));
+    let format = match log.format {
+        LogFormat::Pretty => tracing_subscriber::fmt::layer().pretty().boxed(),
+        LogFormat::Compact => tracing_subscriber::fmt::layer().compact().boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer().json().boxed(),
+    };
+
+    if tracing_subscriber::registry()
+        .with(format.with_filter(filter))
+        .with(QueryLayer.with_filter(Targets::new().with_target(QUERY_TARGET, Level::TRACE)))
+        .try_init()
+        .is_ok()
+    {
+        let _ = FILTER.set(handle);
+    }
+}
+
+/// Changes the level of the subscriber installed by [`init`].
+pub fn set_level(level: &str) {
+    if let Some(handle) = FILTER.get() {
+        if let Err(e) = handle.reload(filter(level)) {
+            tracing::error!("Failed to change the log level: {}", e);
+        }
+    }
+}
+
+fn filter(level: &str) -> EnvFilter {
+    EnvFilter::try_new(level).unwrap_or_else(|e| {
+        eprintln!("Invalid log level `{}`, using `info`: {}", level, e);
+        EnvFilter::new("info")
+    })
+}
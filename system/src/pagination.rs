@@ -0,0 +1,40 @@
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, OriginalUri},
+    http::request::Parts,
+};
+use database::PageRequest;
+
+use crate::Error;
+
+/// Extracts a [`PageRequest`] from the query string, answering `400` when
+/// it is malformed.
+///
+/// ```ignore
+/// pub async fn index(
+///     State(state): State<AppState>,
+///     Pagination(request): Pagination,
+/// ) -> Response<impl IntoResponse> {
+///     let page = PostDB::paginate(state.db.get_pool(), &request).await?;
+///     Ok(Json(page))
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Pagination(pub PageRequest);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Pagination {
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Error> {
+        // Nested routers see their path without the prefix, links need it.
+        let uri = match parts.extensions.get::<OriginalUri>() {
+            Some(OriginalUri(uri)) => uri,
+            None => &parts.uri,
+        };
+        Ok(Pagination(PageRequest::from_query(
+            uri.path(),
+            uri.query().unwrap_or_default(),
+        )?))
+    }
+}
@@ -0,0 +1,586 @@
+use std::{
+    ffi::c_int,
+    io::{self, Read, Write},
+    os::{
+        fd::RawFd,
+        unix::{net::UnixStream, process::CommandExt},
+    },
+    path::PathBuf,
+    process::Command,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        OnceLock,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use libc::pid_t;
+use serde::Serialize;
+
+use crate::listener::Listeners;
+
+/// The listening sockets handed over by the previous generation, one
+/// `<fd>=<address>` per line. Not `JAYA_`-prefixed, which the loader would
+/// read as config.
+const LISTEN_FDS: &str = "UPGRADE_LISTEN_FDS";
+/// The previous generation, stopped once this one serves.
+const REPLACE_PID: &str = "UPGRADE_REPLACE_PID";
+
+/// Time workers get on top of `server.shutdown_timeout` before the master
+/// kills them.
+const KILL_GRACE: Duration = Duration::from_secs(5);
+
+/// A worker running this long has started fine, so crashing afterwards
+/// restarts it right away.
+const STABLE_AFTER: Duration = Duration::from_secs(30);
+
+/// The longest a crashed worker waits to be restarted.
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(30);
+
+/// The signals the master waits for, `SIGALRM` being its clock.
+const SIGNALS: [c_int; 8] = [
+    libc::SIGTERM,
+    libc::SIGINT,
+    libc::SIGQUIT,
+    libc::SIGHUP,
+    libc::SIGUSR1,
+    libc::SIGUSR2,
+    libc::SIGCHLD,
+    libc::SIGALRM,
+];
+
+/// The executable as of startup, as a deploy may replace the file later.
+static EXECUTABLE: OnceLock<Option<PathBuf>> = OnceLock::new();
+
+/// Records the executable [`reexec`] runs, before a deploy replaces it.
+pub(crate) fn remember_executable() {
+    EXECUTABLE.get_or_init(|| std::env::current_exe().ok());
+}
+
+/// Takes the descriptors of the listening sockets handed over by the
+/// previous generation, if this process is a new one started by [`reexec`],
+/// along with the configured address each was bound for.
+pub(crate) fn inherited_fds() -> Vec<(RawFd, String)> {
+    let Ok(fds) = std::env::var(LISTEN_FDS) else {
+        return Vec::new();
+    };
+    std::env::remove_var(LISTEN_FDS);
+    parse_fds(&fds)
+}
+
+/// The descriptors handed to a new generation and the address of each, one
+/// `fd=address` per line.
+fn format_fds(fds: &[(RawFd, String)]) -> String {
+    fds.iter()
+        .map(|(fd, address)| format!("{}={}", fd, address))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn parse_fds(fds: &str) -> Vec<(RawFd, String)> {
+    fds.lines()
+        .filter_map(|line| {
+            let (fd, address) = line.split_once('=')?;
+            Some((fd.parse().ok()?, address.to_string()))
+        })
+        .collect()
+}
+
+/// Takes the process id of the previous generation, waiting for this one
+/// to serve before it stops.
+pub(crate) fn replaced_pid() -> Option<pid_t> {
+    let pid = std::env::var(REPLACE_PID).ok()?.parse().ok()?;
+    std::env::remove_var(REPLACE_PID);
+    Some(pid)
+}
+
+/// Tells the previous generation to drain its requests and exit, now that
+/// this one accepts connections on the same socket.
+fn replace(pid: pid_t) {
+    tracing::info!("Serving, stopping the previous generation (PID {})", pid);
+    // SAFETY: kill has no memory safety requirements.
+    unsafe { libc::kill(pid, libc::SIGTERM) };
+}
+
+/// Starts a new generation of the server: the executable again, with the
+/// same arguments, serving on `listeners` alongside this process until it
+/// tells this one to stop.
+pub(crate) fn reexec(listeners: &Listeners) -> io::Result<u32> {
+    let executable =
+        EXECUTABLE.get().cloned().flatten().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "the executable path is unknown")
+        })?;
+    let handed = listeners.fds();
+    let list = format_fds(&handed);
+    let fds: Vec<RawFd> = handed.into_iter().map(|(fd, _)| fd).collect();
+
+    let mut command = Command::new(executable);
+    command
+        .args(std::env::args_os().skip(1))
+        .env(LISTEN_FDS, list)
+        .env(REPLACE_PID, std::process::id().to_string());
+    // SAFETY: fcntl is async-signal-safe, so it may run between fork and
+    // exec. Clearing close-on-exec lets the new executable keep the sockets.
+    unsafe {
+        command.pre_exec(move || {
+            for fd in &fds {
+                if libc::fcntl(*fd, libc::F_SETFD, 0) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+    Ok(command.spawn()?.id())
+}
+
+/// Tells the one who started the server that it accepts connections.
+pub(crate) enum Ready {
+    /// A server running alone, which replaces the previous generation
+    /// itself, if any.
+    Process(Option<pid_t>),
+    /// A prefork worker, reporting to the [`Master`].
+    Worker(UnixStream),
+}
+
+impl Ready {
+    pub(crate) fn notify(self) {
+        match self {
+            Ready::Process(Some(pid)) => replace(pid),
+            Ready::Process(None) => {}
+            Ready::Worker(stream) => {
+                let _ = (&stream).write_all(&[1]);
+            }
+        }
+    }
+
+    /// Whether this server handles `SIGUSR2` itself, rather than a master.
+    pub(crate) fn upgrades(&self) -> bool {
+        matches!(self, Ready::Process(_))
+    }
+}
+
+/// Statistics of the prefork master, as seen from any of its workers.
+#[derive(Clone, Debug, Serialize)]
+pub struct WorkerStats {
+    pub master_pid: u32,
+    /// Workers the master keeps running.
+    pub workers: u32,
+    pub running: u32,
+    /// Workers started again after exiting unexpectedly.
+    pub restarts: u64,
+    /// Unix time of the last restart, in seconds.
+    pub last_restart: Option<u64>,
+}
+
+/// The statistics of the master, in memory shared with its workers.
+#[derive(Default)]
+struct Shared {
+    master_pid: AtomicU32,
+    workers: AtomicU32,
+    running: AtomicU32,
+    restarts: AtomicU64,
+    last_restart: AtomicU64,
+}
+
+static SHARED: OnceLock<&'static Shared> = OnceLock::new();
+
+impl Shared {
+    /// Maps the statistics into memory that forked workers share with the
+    /// master rather than copy.
+    fn map() -> io::Result<&'static Shared> {
+        // SAFETY: the mapping is anonymous, sized and aligned for Shared
+        // (mmap returns page aligned memory), initialized before being
+        // referenced, and never unmapped.
+        unsafe {
+            let shared = libc::mmap(
+                std::ptr::null_mut(),
+                std::mem::size_of::<Shared>(),
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_ANON,
+                -1,
+                0,
+            );
+            if shared == libc::MAP_FAILED {
+                return Err(io::Error::last_os_error());
+            }
+            let shared = shared.cast::<Shared>();
+            shared.write(Shared::default());
+            Ok(&*shared)
+        }
+    }
+}
+
+/// The statistics of the prefork master, `None` when the server runs
+/// without one.
+pub fn worker_stats() -> Option<WorkerStats> {
+    let shared = SHARED.get()?;
+    let last_restart = shared.last_restart.load(Ordering::Relaxed);
+    Some(WorkerStats {
+        master_pid: shared.master_pid.load(Ordering::Relaxed),
+        workers: shared.workers.load(Ordering::Relaxed),
+        running: shared.running.load(Ordering::Relaxed),
+        restarts: shared.restarts.load(Ordering::Relaxed),
+        last_restart: (last_restart > 0).then_some(last_restart),
+    })
+}
+
+/// A worker the master keeps running.
+struct Slot {
+    pid: Option<pid_t>,
+    started: Instant,
+    /// Crashes in a row, each soon after the worker started.
+    failures: u32,
+    restart_at: Option<Instant>,
+}
+
+impl Slot {
+    /// Schedules the restart of a worker that exited or failed to start at
+    /// `now`, returning how long it waits.
+    fn schedule_restart(&mut self, now: Instant) -> Duration {
+        if now.duration_since(self.started) >= STABLE_AFTER {
+            self.failures = 0;
+        }
+        let delay = restart_delay(self.failures);
+        self.failures += 1;
+        self.restart_at = Some(now + delay);
+        delay
+    }
+}
+
+/// The parent of the prefork workers, supervising them. It starts them,
+/// restarts those exiting unexpectedly, forwards `SIGTERM`, `SIGINT` and
+/// `SIGQUIT` as `SIGTERM` so they drain their requests, kills those still
+/// running after the drain timeout, and exits once they all have. `SIGUSR2`
+/// starts a new generation with [`reexec`], `SIGUSR1` logs [`worker_stats`].
+///
+/// A worker crashing within [`STABLE_AFTER`] of starting is restarted after
+/// a delay, doubled on every such crash in a row up to
+/// [`MAX_RESTART_DELAY`], so one failing on startup, e.g. while the
+/// database is down, does not fork in a loop.
+///
+/// The master stays single threaded, without a runtime, and takes signals
+/// with `sigwait` rather than handlers, so forking from it is safe.
+pub(crate) struct Master {
+    pub(crate) workers: u32,
+    pub(crate) shutdown_timeout: Duration,
+    /// Forwards `SIGHUP`, which reloads the config of the workers but
+    /// terminates them unless hot reloading is on.
+    pub(crate) reload: bool,
+    /// The previous generation, stopped once every worker serves.
+    pub(crate) replaces: Option<pid_t>,
+}
+
+impl Master {
+    /// Forks the workers, each running `worker` with its number and the
+    /// [`Ready`] to notify, and exiting with the code it returns.
+    pub(crate) fn run(
+        mut self,
+        listeners: &Listeners,
+        worker: impl Fn(u32, Ready) -> i32,
+    ) -> io::Result<()> {
+        let signals = SignalSet::block()?;
+        let (mut ready, notifier) = UnixStream::pair()?;
+        ready.set_nonblocking(true)?;
+        let stats = Shared::map()?;
+        stats
+            .master_pid
+            .store(std::process::id(), Ordering::Relaxed);
+        stats.workers.store(self.workers, Ordering::Relaxed);
+        let _ = SHARED.set(stats);
+
+        let reload = self.reload;
+        let spawn = |n: u32| -> io::Result<pid_t> {
+            let notifier = notifier.try_clone()?;
+            signals.fork(reload, || worker(n, Ready::Worker(notifier)))
+        };
+
+        let mut slots = Vec::new();
+        for n in 0..self.workers {
+            slots.push(Slot {
+                pid: Some(spawn(n)?),
+                started: Instant::now(),
+                failures: 0,
+                restart_at: None,
+            });
+        }
+        stats.running.store(self.workers, Ordering::Relaxed);
+        tracing::info!(
+            "Master (PID {}) started {} workers",
+            std::process::id(),
+            self.workers
+        );
+
+        let mut serving = 0;
+        let mut deadline = None;
+        let mut upgrade = None;
+        // SAFETY: alarm has no memory safety requirements.
+        unsafe { libc::alarm(1) };
+
+        while deadline.is_none() || slots.iter().any(|slot| slot.pid.is_some()) {
+            let pids: Vec<pid_t> = slots.iter().filter_map(|slot| slot.pid).collect();
+            match signals.wait() {
+                libc::SIGTERM | libc::SIGINT | libc::SIGQUIT if deadline.is_none() => {
+                    tracing::info!(
+                        "Shutting down, draining requests for up to {}s",
+                        self.shutdown_timeout.as_secs()
+                    );
+                    deadline = Some(Instant::now() + self.shutdown_timeout + KILL_GRACE);
+                    send(&pids, libc::SIGTERM);
+                }
+                libc::SIGHUP if self.reload => send(&pids, libc::SIGHUP),
+                libc::SIGUSR1 => {
+                    if let Some(stats) = worker_stats() {
+                        tracing::info!(
+                            "{} of {} workers running, {} restarts",
+                            stats.running,
+                            stats.workers,
+                            stats.restarts
+                        );
+                    }
+                }
+                libc::SIGUSR2 if deadline.is_none() => match reexec(listeners) {
+                    Ok(pid) => {
+                        tracing::info!("Started a new generation (PID {})", pid);
+                        upgrade = Some(pid as pid_t);
+                    }
+                    Err(e) => tracing::error!("Failed to start a new generation: {}", e),
+                },
+                libc::SIGALRM => {
+                    // SAFETY: alarm has no memory safety requirements.
+                    unsafe { libc::alarm(1) };
+
+                    let mut buf = [0; 64];
+                    while let Ok(read @ 1..) = ready.read(&mut buf) {
+                        serving += read;
+                    }
+                    if serving >= slots.len() {
+                        if let Some(pid) = self.replaces.take() {
+                            replace(pid);
+                        }
+                    }
+
+                    if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                        tracing::warn!("Killing {} workers still draining", pids.len());
+                        send(&pids, libc::SIGKILL);
+                        deadline = Some(Instant::now() + KILL_GRACE);
+                    }
+                }
+                _ => {}
+            }
+
+            // SIGCHLD may be discarded rather than queued on some systems,
+            // so children are reaped after every signal.
+            while let Some((pid, status)) = reap() {
+                if upgrade == Some(pid) {
+                    tracing::error!(
+                        "New generation (PID {}) exited with {}, this one keeps serving",
+                        pid,
+                        status
+                    );
+                    upgrade = None;
+                    continue;
+                }
+                let Some((n, slot)) = slots
+                    .iter_mut()
+                    .enumerate()
+                    .find(|(_, slot)| slot.pid == Some(pid))
+                else {
+                    continue;
+                };
+                slot.pid = None;
+                if deadline.is_some() {
+                    tracing::info!("Worker {} (PID {}) exited with {}", n, pid, status);
+                    continue;
+                }
+
+                let delay = slot.schedule_restart(Instant::now());
+                tracing::error!(
+                    "Worker {} (PID {}) exited unexpectedly with {}, restarting it in {}s",
+                    n,
+                    pid,
+                    status,
+                    delay.as_secs()
+                );
+            }
+
+            if deadline.is_none() {
+                for (n, slot) in slots.iter_mut().enumerate() {
+                    if slot.restart_at.is_some_and(|at| Instant::now() >= at) {
+                        slot.restart_at = None;
+                        slot.started = Instant::now();
+                        match spawn(n as u32) {
+                            Ok(pid) => {
+                                slot.pid = Some(pid);
+                                stats.restarts.fetch_add(1, Ordering::Relaxed);
+                                stats.last_restart.store(unix_time(), Ordering::Relaxed);
+                                tracing::info!("Restarted worker {} (PID {})", n, pid);
+                            }
+                            Err(e) => {
+                                slot.schedule_restart(Instant::now());
+                                tracing::error!("Failed to restart worker {}: {}", n, e);
+                            }
+                        }
+                    }
+                }
+            }
+
+            let running = slots.iter().filter(|slot| slot.pid.is_some()).count();
+            stats.running.store(running as u32, Ordering::Relaxed);
+        }
+
+        tracing::info!(
+            "Master (PID {}) exiting after {} restarts",
+            std::process::id(),
+            stats.restarts.load(Ordering::Relaxed)
+        );
+        Ok(())
+    }
+}
+
+/// Time to wait before restarting a worker that crashed `failures` times in
+/// a row: none the first time, then 1 second doubled every time.
+fn restart_delay(failures: u32) -> Duration {
+    match failures {
+        0 => Duration::ZERO,
+        n => Duration::from_secs(1 << (n - 1).min(5)).min(MAX_RESTART_DELAY),
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+struct SignalSet(libc::sigset_t);
+
+impl SignalSet {
+    /// Blocks [`SIGNALS`], so they are left for [`SignalSet::wait`] instead
+    /// of their default action.
+    fn block() -> io::Result<Self> {
+        // SAFETY: the set is initialized by sigemptyset before use.
+        unsafe {
+            let mut set = std::mem::zeroed();
+            libc::sigemptyset(&mut set);
+            for signal in SIGNALS {
+                libc::sigaddset(&mut set, signal);
+            }
+            match libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut()) {
+                0 => Ok(SignalSet(set)),
+                e => Err(io::Error::from_raw_os_error(e)),
+            }
+        }
+    }
+
+    fn wait(&self) -> c_int {
+        let mut signal = 0;
+        // SAFETY: both pointers are valid for the duration of the call.
+        unsafe { libc::sigwait(&self.0, &mut signal) };
+        signal
+    }
+
+    /// Forks a child running `f`, with the signals unblocked again, and
+    /// exits it with the code `f` returns rather than returning here. With
+    /// `ignore_hangup`, `SIGHUP` is ignored until the child handles it
+    /// itself, so one forwarded while the child starts does not end it.
+    fn fork(&self, ignore_hangup: bool, f: impl FnOnce() -> i32) -> io::Result<pid_t> {
+        // SAFETY: the master has a single thread, so the child does not
+        // inherit locks held by others.
+        match unsafe { libc::fork() } {
+            -1 => Err(io::Error::last_os_error()),
+            0 => {
+                if ignore_hangup {
+                    // SAFETY: signal has no memory safety requirements.
+                    unsafe { libc::signal(libc::SIGHUP, libc::SIG_IGN) };
+                }
+                // SAFETY: the set was initialized in SignalSet::block.
+                unsafe { libc::pthread_sigmask(libc::SIG_UNBLOCK, &self.0, std::ptr::null_mut()) };
+                // A panic must not unwind into the master's code.
+                let code = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f));
+                std::process::exit(code.unwrap_or(101))
+            }
+            pid => Ok(pid),
+        }
+    }
+}
+
+fn send(pids: &[pid_t], signal: c_int) {
+    for pid in pids {
+        // SAFETY: kill has no memory safety requirements.
+        unsafe { libc::kill(*pid, signal) };
+    }
+}
+
+/// An exited child and how it exited, if any.
+fn reap() -> Option<(pid_t, String)> {
+    let mut status = 0;
+    // SAFETY: status is valid for the duration of the call.
+    let pid = unsafe { libc::waitpid(-1, &mut status, libc::WNOHANG) };
+    if pid <= 0 {
+        return None;
+    }
+    let status = if libc::WIFSIGNALED(status) {
+        format!("signal {}", libc::WTERMSIG(status))
+    } else {
+        format!("code {}", libc::WEXITSTATUS(status))
+    };
+    Some((pid, status))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hands_over_fds_with_their_address() {
+        let fds = vec![
+            (3, "127.0.0.1:3000".to_string()),
+            (4, "unix:/run/jaya/jaya.sock".to_string()),
+            (5, "[::1]:3001".to_string()),
+        ];
+        assert_eq!(parse_fds(&format_fds(&fds)), fds);
+        assert_eq!(parse_fds(""), []);
+        assert_eq!(
+            parse_fds("3\nx=unix:a\n4=systemd:web"),
+            [(4, "systemd:web".to_string())]
+        );
+    }
+
+    #[test]
+    fn doubles_the_restart_delay_up_to_the_maximum() {
+        let delays: Vec<u64> = (0..9).map(|n| restart_delay(n).as_secs()).collect();
+        assert_eq!(delays, [0, 1, 2, 4, 8, 16, 30, 30, 30]);
+        assert_eq!(restart_delay(u32::MAX), MAX_RESTART_DELAY);
+    }
+
+    #[test]
+    fn backs_off_on_crashes_in_a_row_and_resets_once_stable() {
+        let start = Instant::now();
+        let mut slot = Slot {
+            pid: None,
+            started: start,
+            failures: 0,
+            restart_at: None,
+        };
+
+        let crash = Duration::from_secs(1);
+        let delays: Vec<u64> = (0..4)
+            .map(|_| slot.schedule_restart(start + crash).as_secs())
+            .collect();
+        assert_eq!(delays, [0, 1, 2, 4]);
+        assert_eq!(
+            slot.restart_at,
+            Some(start + crash + Duration::from_secs(4))
+        );
+
+        // Running for STABLE_AFTER counts as a fresh start.
+        assert_eq!(slot.schedule_restart(start + STABLE_AFTER), Duration::ZERO);
+        assert_eq!(slot.failures, 1);
+        slot.started = start + STABLE_AFTER;
+        assert_eq!(
+            slot.schedule_restart(start + STABLE_AFTER + crash),
+            Duration::from_secs(1)
+        );
+    }
+}
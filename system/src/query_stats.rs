@@ -0,0 +1,45 @@
+use axum::{extract::State, http::Request, middleware::Next, response::Response};
+
+/// What [`query_stats_layer`] reports, from the `database` section.
+#[derive(Clone, Copy)]
+pub(crate) struct QueryReport {
+    /// Runs of the same `SELECT` that make it a likely N+1 query, `0` to
+    /// not look for them.
+    pub(crate) n_plus_one_threshold: usize,
+}
+
+/// Counts the statements run by each request, logging them at `debug`, and
+/// warns about `SELECT`s repeated often enough to be N+1 queries.
+pub(crate) async fn query_stats_layer<B>(
+    State(report): State<QueryReport>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let (response, stats) = database::track(next.run(request)).await;
+
+    if stats.count > 0 {
+        tracing::debug!(
+            queries = stats.count,
+            slow = stats.slow,
+            rows = stats.rows_returned,
+            "{} {} ran {} queries in {:?}",
+            method,
+            path,
+            stats.count,
+            stats.elapsed
+        );
+    }
+    for (sql, runs) in stats.repeated(report.n_plus_one_threshold) {
+        tracing::warn!(
+            "Possible N+1 query: {} {} ran `{}` {} times, load the rows in a single query instead",
+            method,
+            path,
+            sql,
+            runs
+        );
+    }
+
+    response
+}
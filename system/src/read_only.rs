@@ -0,0 +1,13 @@
+use axum::{http::Request, middleware::Next, response::Response};
+
+/// Sends the queries of the routes it wraps to the read replicas, through
+/// [`database::read_only`]. A [`Tx`](crate::Tx) still uses the primary.
+///
+/// ```ignore
+/// Router::new()
+///     .route("/", get(post::index))
+///     .route_layer(middleware::from_fn(read_only_layer))
+/// ```
+pub async fn read_only_layer<B>(request: Request<B>, next: Next<B>) -> Response {
+    database::read_only(next.run(request)).await
+}
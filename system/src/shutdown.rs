@@ -0,0 +1,61 @@
+use std::{io, sync::OnceLock};
+
+use tokio::{
+    signal::unix::{signal, Signal, SignalKind},
+    sync::watch,
+};
+
+use crate::{listener::Listeners, process};
+
+static SHUTDOWN: OnceLock<watch::Sender<bool>> = OnceLock::new();
+
+fn sender() -> &'static watch::Sender<bool> {
+    SHUTDOWN.get_or_init(|| watch::channel(false).0)
+}
+
+/// Resolves once the server starts shutting down. Responses that never end
+/// on their own, such as event streams, should end then rather than hold
+/// the shutdown until `server.shutdown_timeout`.
+pub async fn shutting_down() {
+    let _ = sender().subscribe().wait_for(|stopping| *stopping).await;
+}
+
+/// The signals stopping the server.
+pub(crate) struct Shutdown {
+    terminate: Signal,
+    interrupt: Signal,
+    upgrade: Signal,
+    /// Handed to a new generation on `SIGUSR2`, unless a master does that.
+    listeners: Option<Listeners>,
+}
+
+impl Shutdown {
+    pub(crate) fn new(listeners: Option<Listeners>) -> io::Result<Self> {
+        Ok(Self {
+            terminate: signal(SignalKind::terminate())?,
+            interrupt: signal(SignalKind::interrupt())?,
+            upgrade: signal(SignalKind::user_defined2())?,
+            listeners,
+        })
+    }
+
+    /// Waits for `SIGTERM` or `SIGINT`, then wakes [`shutting_down`].
+    /// `SIGUSR2` meanwhile starts a new generation, which sends `SIGTERM`
+    /// once it serves.
+    pub(crate) async fn wait(mut self) {
+        loop {
+            tokio::select! {
+                _ = self.terminate.recv() => break,
+                _ = self.interrupt.recv() => break,
+                _ = self.upgrade.recv() => match &self.listeners {
+                    Some(listeners) => match process::reexec(listeners) {
+                        Ok(pid) => tracing::info!("Started a new generation (PID {})", pid),
+                        Err(e) => tracing::error!("Failed to start a new generation: {}", e),
+                    },
+                    None => tracing::warn!("Ignoring SIGUSR2, which the master handles"),
+                },
+            }
+        }
+        sender().send_replace(true);
+    }
+}
@@ -0,0 +1,37 @@
+use std::convert::Infallible;
+
+use axum::response::sse::{Event, KeepAlive, Sse};
+use database::Subscription;
+use futures_util::stream::{self, Stream, StreamExt};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::shutting_down;
+
+/// Forwards the events of `subscription` to the browser as server-sent
+/// events named after the channel, with the event as JSON data. The stream
+/// ends when the server shuts down, so it does not hold up the drain.
+///
+/// ```ignore
+/// pub async fn events(State(state): State<AppState>) -> Response<impl IntoResponse> {
+///     Ok(sse(state.db.subscribe::<PostEvent>("posts").await?))
+/// }
+/// ```
+pub fn sse<T>(subscription: Subscription<T>) -> Sse<impl Stream<Item = Result<Event, Infallible>>>
+where
+    T: Serialize + DeserializeOwned + Send + 'static,
+{
+    let events = stream::unfold(subscription, |mut subscription| async move {
+        loop {
+            let event = subscription.recv().await?;
+            match Event::default()
+                .event(subscription.channel())
+                .json_data(&event)
+            {
+                Ok(event) => return Some((Ok(event), subscription)),
+                Err(e) => tracing::warn!("Failed to forward an event: {}", e),
+            }
+        }
+    });
+
+    Sse::new(events.take_until(shutting_down())).keep_alive(KeepAlive::default())
+}
@@ -0,0 +1,223 @@
+use std::{
+    fs::File,
+    io::{self, BufReader},
+    net::TcpListener,
+    path::Path,
+    pin::Pin,
+    sync::{Arc, RwLock},
+    task::{Context, Poll},
+    time::{Duration, SystemTime},
+};
+
+use axum::{
+    http::{header, HeaderMap, StatusCode, Uri},
+    response::{IntoResponse, Redirect},
+};
+use hyper::server::accept::Accept;
+use tokio::{net::TcpStream, sync::mpsc};
+use tokio_rustls::{
+    rustls::{
+        server::{ClientHello, ResolvesServerCert},
+        sign::{self, CertifiedKey},
+        Certificate, PrivateKey, ServerConfig,
+    },
+    server::TlsStream,
+    TlsAcceptor,
+};
+
+/// Handshaken connections waiting for the server to take them.
+const BACKLOG: usize = 64;
+
+/// When the certificate and key files were last changed.
+type Modified = (Option<SystemTime>, Option<SystemTime>);
+
+/// The certificate served to every client, reloaded when its files change.
+pub(crate) struct Certificates {
+    tls: config::Tls,
+    current: RwLock<(Arc<CertifiedKey>, Modified)>,
+}
+
+impl Certificates {
+    pub(crate) fn load(tls: &config::Tls) -> io::Result<Arc<Self>> {
+        let modified = modified(tls);
+        Ok(Arc::new(Self {
+            tls: tls.clone(),
+            current: RwLock::new((Arc::new(certified_key(tls)?), modified)),
+        }))
+    }
+
+    /// Checks the files every `server.tls.reload_interval` seconds, swapping
+    /// in the certificate when they changed. A certificate failing to load,
+    /// e.g. while only one of the files is replaced, is retried on the next
+    /// check while the current one keeps being served.
+    pub(crate) async fn watch(self: Arc<Self>) {
+        if self.tls.reload_interval == 0 {
+            return;
+        }
+
+        let mut interval = tokio::time::interval(Duration::from_secs(self.tls.reload_interval));
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+
+            let modified = modified(&self.tls);
+            if modified == self.current.read().unwrap().1 {
+                continue;
+            }
+            match certified_key(&self.tls) {
+                Ok(key) => {
+                    *self.current.write().unwrap() = (Arc::new(key), modified);
+                    tracing::info!("Reloaded the TLS certificate");
+                }
+                Err(e) => tracing::warn!("Failed to reload the TLS certificate: {}", e),
+            }
+        }
+    }
+}
+
+impl ResolvesServerCert for Certificates {
+    fn resolve(&self, _: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap().0.clone())
+    }
+}
+
+fn modified(tls: &config::Tls) -> Modified {
+    let modified = |path: &Path| path.metadata().and_then(|meta| meta.modified()).ok();
+    (modified(&tls.cert), modified(&tls.key))
+}
+
+fn certified_key(tls: &config::Tls) -> io::Result<CertifiedKey> {
+    let invalid = |path: &Path, message: &dyn std::fmt::Display| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {}", path.display(), message),
+        )
+    };
+    let read = |path: &Path| {
+        File::open(path)
+            .map(BufReader::new)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+    };
+
+    let certs: Vec<Certificate> = rustls_pemfile::certs(&mut read(&tls.cert)?)?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    if certs.is_empty() {
+        return Err(invalid(&tls.cert, &"no certificate found"));
+    }
+
+    let key = rustls_pemfile::read_all(&mut read(&tls.key)?)?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| invalid(&tls.key, &"no private key found"))?;
+    let key = sign::any_supported_type(&key).map_err(|e| invalid(&tls.key, &e))?;
+
+    Ok(CertifiedKey::new(certs, key))
+}
+
+/// Serves `certificates`, offering HTTP/2 through ALPN when `http2` is on.
+pub(crate) fn acceptor(certificates: Arc<Certificates>, http2: bool) -> TlsAcceptor {
+    let mut config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_cert_resolver(certificates);
+    if http2 {
+        config.alpn_protocols.push(b"h2".to_vec());
+    }
+    config.alpn_protocols.push(b"http/1.1".to_vec());
+    TlsAcceptor::from(Arc::new(config))
+}
+
+/// The connections of a listener, past their TLS handshake. Handshakes run
+/// concurrently, so a slow client does not hold up the others, and are
+/// dropped after `handshake_timeout`.
+pub(crate) struct TlsIncoming {
+    connections: mpsc::Receiver<TlsStream<TcpStream>>,
+}
+
+impl TlsIncoming {
+    pub(crate) fn new(
+        listener: TcpListener,
+        acceptor: TlsAcceptor,
+        handshake_timeout: Duration,
+    ) -> io::Result<Self> {
+        listener.set_nonblocking(true)?;
+        let listener = tokio::net::TcpListener::from_std(listener)?;
+        let (sender, connections) = mpsc::channel(BACKLOG);
+
+        tokio::spawn(async move {
+            loop {
+                // Stops accepting once the server shuts down, leaving new
+                // connections to the next generation, if any.
+                let stream = tokio::select! {
+                    _ = sender.closed() => break,
+                    accepted = listener.accept() => match accepted {
+                        Ok((stream, _)) => stream,
+                        Err(e) => {
+                            tracing::warn!("Failed to accept a connection: {}", e);
+                            tokio::time::sleep(Duration::from_millis(100)).await;
+                            continue;
+                        }
+                    },
+                };
+
+                let acceptor = acceptor.clone();
+                let sender = sender.clone();
+                tokio::spawn(async move {
+                    match tokio::time::timeout(handshake_timeout, acceptor.accept(stream)).await {
+                        Ok(Ok(stream)) => {
+                            let _ = sender.send(stream).await;
+                        }
+                        Ok(Err(e)) => tracing::debug!("TLS handshake failed: {}", e),
+                        Err(_) => tracing::debug!("TLS handshake timed out"),
+                    }
+                });
+            }
+        });
+
+        Ok(Self { connections })
+    }
+}
+
+impl Accept for TlsIncoming {
+    type Conn = TlsStream<TcpStream>;
+    type Error = io::Error;
+
+    fn poll_accept(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        self.connections.poll_recv(cx).map(|stream| stream.map(Ok))
+    }
+}
+
+/// Answers every request with a permanent redirect to the same URL over
+/// HTTPS on `port`.
+pub(crate) fn redirect(port: u16) -> axum::Router {
+    axum::Router::new().fallback(move |headers: HeaderMap, uri: Uri| async move {
+        match https_url(&headers, &uri, port) {
+            Some(url) => Redirect::permanent(&url).into_response(),
+            None => StatusCode::BAD_REQUEST.into_response(),
+        }
+    })
+}
+
+fn https_url(headers: &HeaderMap, uri: &Uri, port: u16) -> Option<String> {
+    let host = headers.get(header::HOST)?.to_str().ok()?;
+    let host = match host.rsplit_once(':') {
+        Some((name, port)) if port.parse::<u16>().is_ok() => name,
+        _ => host,
+    };
+    let port = match port {
+        443 => String::new(),
+        port => format!(":{}", port),
+    };
+    let path = uri.path_and_query().map_or("/", |path| path.as_str());
+    Some(format!("https://{}{}{}", host, port, path))
+}
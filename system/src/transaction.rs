@@ -0,0 +1,109 @@
+use std::{
+    ops::{Deref, DerefMut},
+    sync::Arc,
+};
+
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{request::Parts, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use database::{Connection, Transaction};
+use tokio::sync::{Mutex, OwnedMutexGuard};
+
+use crate::{AppState, Error};
+
+/// The transaction of a request, started by the first [`Tx`] extractor.
+type Slot = Arc<Mutex<Option<Transaction>>>;
+
+/// A transaction scoped to the request, for handlers that write several
+/// tables atomically.
+///
+/// It is begun when extracted, and committed by [`transaction_layer`] once
+/// the handler answers with a success or redirect, or rolled back on an
+/// error status. Only the status counts, so a handler turning an error into
+/// a `200` commits what it wrote before the error.
+///
+/// Extract it at most once per handler: a second `Tx` taken while the first
+/// is alive fails with a `500`, as waiting for the first would never end.
+///
+/// ```ignore
+/// pub async fn save(mut tx: Tx, Json(payload): Json<Payload>) -> Response<impl IntoResponse> {
+///     let post = PostDB::insert(&mut *tx, payload.into()).await?;
+///     TagDB::attach(&mut *tx, post.id, &payload.tags).await?;
+///     Ok(Json(post))
+/// }
+/// ```
+pub struct Tx(OwnedMutexGuard<Option<Transaction>>);
+
+#[async_trait]
+impl FromRequestParts<AppState> for Tx {
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Error> {
+        let slot = parts.extensions.get::<Slot>().cloned().ok_or_else(|| {
+            Error::Misconfigured(
+                "Tx extracted on a route without the transaction layer".to_string(),
+            )
+        })?;
+
+        // Only another `Tx` of this request holds the lock while the handler
+        // runs.
+        let mut guard = slot.try_lock_owned().map_err(|_| {
+            Error::Misconfigured("Tx extracted twice in the same request".to_string())
+        })?;
+        if guard.is_none() {
+            *guard = Some(state.db.begin().await?);
+        }
+        Ok(Tx(guard))
+    }
+}
+
+impl Deref for Tx {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.0
+            .as_ref()
+            .expect("transaction taken before the request ended")
+    }
+}
+
+impl DerefMut for Tx {
+    fn deref_mut(&mut self) -> &mut Connection {
+        self.0
+            .as_mut()
+            .expect("transaction taken before the request ended")
+    }
+}
+
+/// Commits the transaction of a [`Tx`] extracted during the request when
+/// the response is a success or redirect, and rolls it back otherwise.
+///
+/// [`System`](crate::System) adds it to every route, it only needs adding
+/// by hand to routers served some other way:
+/// `router.layer(axum::middleware::from_fn(transaction_layer))`.
+pub async fn transaction_layer<B>(mut request: Request<B>, next: Next<B>) -> Response {
+    let slot = Slot::default();
+    request.extensions_mut().insert(slot.clone());
+
+    let response = next.run(request).await;
+
+    let Some(tx) = slot.lock().await.take() else {
+        return response;
+    };
+    let status = response.status();
+    if status.is_client_error() || status.is_server_error() {
+        if let Err(e) = tx.rollback().await {
+            tracing::warn!("Failed to roll back request transaction: {}", e);
+        }
+        response
+    } else {
+        match tx.commit().await {
+            Ok(()) => response,
+            Err(e) => Error::Database(e).into_response(),
+        }
+    }
+}